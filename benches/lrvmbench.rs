@@ -56,4 +56,141 @@ mod arithmetic {
     }
 }
 
-criterion_main!(arithmetic::arithmetic);
+mod setup {
+    use lrvm::{assembler::prepend_header, vm};
+
+    use super::*;
+
+    /// add $0 $1 $2, hlt
+    const PROGRAM: [u8; 8] = [1, 0, 1, 2, 5, 0, 0, 0];
+
+    fn run_headerless(c: &mut Criterion) {
+        let clos = || {
+            let mut test_vm = vm::get_test_vm();
+            test_vm.program = PROGRAM.to_vec();
+            test_vm.run_once();
+            test_vm.run_once();
+        };
+
+        c.bench_function("run_headerless", move |b| b.iter(clos));
+    }
+
+    fn run_with_header(c: &mut Criterion) {
+        let clos = || {
+            let mut test_vm = vm::get_test_vm();
+            test_vm.program = prepend_header(PROGRAM.to_vec());
+            test_vm.run();
+        };
+
+        c.bench_function("run_with_header", move |b| b.iter(clos));
+    }
+
+    criterion_group! {
+        name = setup;
+        config = Criterion::default();
+        targets = run_headerless, run_with_header,
+    }
+}
+
+mod dispatch {
+    use lrvm::{assembler::Assembler, vm::VM};
+
+    use super::*;
+
+    /// Counts $2 up to 10000, so nearly all the time goes into decoding and dispatching
+    /// the three instructions of the loop body
+    const TIGHT_LOOP: &str = ".data
+.code
+load $0 #10000
+load $2 #0
+load $3 @test
+test: inc $2
+neq $0 $2
+jmpe $3
+hlt
+";
+
+    /// Compare dispatch changes with `cargo bench -- --save-baseline <name>` before the change
+    /// and `--baseline <name>` after it
+    fn tight_loop(c: &mut Criterion) {
+        let program = Assembler::new().assemble(TIGHT_LOOP).unwrap();
+        c.bench_function("tight_loop", move |b| {
+            b.iter(|| {
+                let mut test_vm = VM::new();
+                test_vm.program = program.clone();
+                test_vm.run();
+            })
+        });
+    }
+
+    criterion_group! {
+        name = dispatch;
+        config = Criterion::default();
+        targets = tight_loop,
+    }
+}
+
+mod prts {
+    use lrvm::{assembler::Assembler, vm::VM};
+
+    use super::*;
+
+    /// Looks up a 1000 character string the way PRTS does, against the byte by byte scan
+    /// it used to do every time
+    fn ro_string_lookup(c: &mut Criterion) {
+        let text = "x".repeat(1000);
+        let source = format!(".data\nmsg: .asciiz '{}'\n.code\nhlt\n", text);
+        let mut test_vm = VM::new();
+        test_vm.program = Assembler::new().assemble(&source).unwrap();
+        // Running loads the read-only section
+        test_vm.run();
+        let ro_data = format!("{}\0", text).into_bytes();
+
+        let mut group = c.benchmark_group("prts");
+        group.bench_function("indexed", |b| {
+            b.iter(|| test_vm.ro_string(criterion::black_box(0)).unwrap().len())
+        });
+        group.bench_function("scan", |b| {
+            b.iter(|| {
+                let offset = criterion::black_box(0);
+                ro_data[offset..].iter().position(|b| *b == 0).unwrap()
+            })
+        });
+        group.finish();
+    }
+
+    criterion_group! {
+        name = prts;
+        config = Criterion::default();
+        targets = ro_string_lookup,
+    }
+}
+
+mod assemble {
+    use lrvm::assembler::{program_parser::program, symbols::SymbolTable};
+
+    use super::*;
+
+    /// Turns a 10000 instruction program into bytecode, which is one buffer rather than
+    /// one per instruction
+    fn program_to_bytes(c: &mut Criterion) {
+        let source = "load $0 #100\nadd $0 $1 $2\nload32 $3 #70000\nhlt\n".repeat(2500);
+        let (_, p) = program(&source).unwrap();
+        let symbols = SymbolTable::new();
+        c.bench_function("program_to_bytes", move |b| b.iter(|| p.to_bytes(&symbols)));
+    }
+
+    criterion_group! {
+        name = assemble;
+        config = Criterion::default();
+        targets = program_to_bytes,
+    }
+}
+
+criterion_main!(
+    arithmetic::arithmetic,
+    setup::setup,
+    dispatch::dispatch,
+    prts::prts,
+    assemble::assemble
+);
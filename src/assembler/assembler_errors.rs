@@ -3,13 +3,57 @@ use std::error::Error;
 
 #[derive(Debug, Clone)]
 pub enum AssemblerError {
-    NoSegmentDeclarationFound { instruction: u32 },
-    StringConstantDeclaredWithoutLabel { instruction: u32 },
+    NoSegmentDeclarationFound {
+        instruction: u32,
+    },
+    StringConstantDeclaredWithoutLabel {
+        instruction: u32,
+    },
     SymbolAlreadyDeclared,
-    UnknownDirectiveFound { directive: String },
+    UnknownDirectiveFound {
+        directive: String,
+    },
     NonOpcodeInOpcodeField,
     InsufficientSections,
-    ParseError { error: String },
+    ParseError {
+        error: String,
+    },
+    SyntaxError {
+        line: usize,
+        column: usize,
+        error: String,
+    },
+    RoSectionTooLarge {
+        size: usize,
+        max: usize,
+    },
+    InvalidDirectiveOperands {
+        directive: String,
+    },
+    FileReadError {
+        path: String,
+        error: String,
+    },
+    FileWriteError {
+        path: String,
+        error: String,
+    },
+    InvalidCommentMarker {
+        marker: String,
+        reason: String,
+    },
+    InvalidEscapeSequence {
+        sequence: String,
+    },
+    ImmediateOutOfRange {
+        instruction: u32,
+        value: i32,
+        min: i32,
+        max: i32,
+    },
+    UnexpectedImmediate {
+        instruction: u32,
+    },
 }
 
 impl fmt::Display for AssemblerError {
@@ -30,6 +74,78 @@ impl fmt::Display for AssemblerError {
             AssemblerError::NonOpcodeInOpcodeField => f.write_str("An non-opcode was found in an opcode field"),
             AssemblerError::InsufficientSections => f.write_str("Less than two sections/segments were found in the code"),
             AssemblerError::ParseError { ref error } => f.write_str(&format!("There was an error parsing the code: {}", error)),
+            AssemblerError::SyntaxError { line, column, ref error } => f.write_str(&format!(
+                "There was an error parsing the code at line {}, column {}: {}",
+                line, column, error
+            )),
+            AssemblerError::RoSectionTooLarge { size, max } => f.write_str(&format!(
+                "The read-only section would grow to {} bytes, more than the allowed {} bytes",
+                size, max
+            )),
+            AssemblerError::InvalidDirectiveOperands { ref directive } => f.write_str(&format!(
+                "The operands given to the .{} directive are not valid for it",
+                directive
+            )),
+            AssemblerError::FileReadError { ref path, ref error } => f.write_str(&format!(
+                "Unable to read source file {}: {}",
+                path, error
+            )),
+            AssemblerError::FileWriteError { ref path, ref error } => f.write_str(&format!(
+                "Unable to write bytecode file {}: {}",
+                path, error
+            )),
+            AssemblerError::InvalidCommentMarker { ref marker, ref reason } => f.write_str(&format!(
+                "Invalid comment marker {:?}: {}",
+                marker, reason
+            )),
+            AssemblerError::InvalidEscapeSequence { ref sequence } => f.write_str(&format!(
+                "Unknown escape sequence {} in a string constant",
+                sequence
+            )),
+            AssemblerError::ImmediateOutOfRange { instruction, value, min, max } => f.write_str(&format!(
+                "Immediate {} doesn't fit the operand, which takes {} to {}. Instruction # was {}",
+                value, min, max, instruction
+            )),
+            AssemblerError::UnexpectedImmediate { instruction } => f.write_str(&format!(
+                "The third operand of this opcode must be a register, not an immediate. Instruction # was {}",
+                instruction
+            )),
+        }
+    }
+}
+
+/// Conditions the assembler notices but can still produce bytecode for
+#[derive(Debug, PartialEq, Clone)]
+pub enum AssemblerWarning {
+    StringConstantWithoutLabel { instruction: u32 },
+    EmptyStringConstant { instruction: u32 },
+    BytesWithoutLabel { instruction: u32 },
+    SpaceWithoutLabel { instruction: u32 },
+    UnknownSectionHeader { name: String },
+}
+
+impl fmt::Display for AssemblerWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            AssemblerWarning::StringConstantWithoutLabel { instruction } => f.write_str(&format!(
+                "Found a string constant with no associated label, it will be ignored. Instruction # was {}",
+                instruction
+            )),
+            AssemblerWarning::EmptyStringConstant { instruction } => f.write_str(&format!(
+                "String constant following an .asciiz was empty. Instruction # was {}",
+                instruction
+            )),
+            AssemblerWarning::BytesWithoutLabel { instruction } => f.write_str(&format!(
+                "Found raw bytes with no associated label, they will be ignored. Instruction # was {}",
+                instruction
+            )),
+            AssemblerWarning::SpaceWithoutLabel { instruction } => f.write_str(&format!(
+                "Found reserved space with no associated label, it will be ignored. Instruction # was {}",
+                instruction
+            )),
+            AssemblerWarning::UnknownSectionHeader { ref name } => {
+                f.write_str(&format!("Found an unknown section header, it will be ignored: {}", name))
+            }
         }
     }
 }
@@ -44,6 +160,15 @@ impl Error for AssemblerError {
             AssemblerError::NonOpcodeInOpcodeField => "A non-opcode was found in an opcode field",
             AssemblerError::InsufficientSections => "Less than two sections/segments were found in the code",
             AssemblerError::ParseError { .. } => "There was an error parsing the code",
+            AssemblerError::SyntaxError { .. } => "There was an error parsing the code",
+            AssemblerError::RoSectionTooLarge { .. } => "The read-only section is larger than allowed",
+            AssemblerError::InvalidDirectiveOperands { .. } => "The operands given to a directive are not valid for it",
+            AssemblerError::FileReadError { .. } => "Unable to read a source file",
+            AssemblerError::FileWriteError { .. } => "Unable to write a bytecode file",
+            AssemblerError::InvalidCommentMarker { .. } => "The comment marker is not usable",
+            AssemblerError::InvalidEscapeSequence { .. } => "A string constant has an unknown escape sequence",
+            AssemblerError::ImmediateOutOfRange { .. } => "An immediate doesn't fit its operand",
+            AssemblerError::UnexpectedImmediate { .. } => "An immediate was found where a register is required",
 
         }
     }
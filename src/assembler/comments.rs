@@ -0,0 +1,87 @@
+use nom::{
+    bytes::complete::tag,
+    character::complete::{multispace0, not_line_ending},
+    combinator::value,
+    error::context,
+    multi::many0_count,
+    sequence::{delimited, pair, preceded},
+    IResult,
+};
+
+use super::Token;
+
+/// Marks the rest of a line as a comment unless `Assembler::with_comment_char` picks another one
+pub const DEFAULT_COMMENT_MARKER: &str = ";";
+
+/// Parses a comment starting with `marker` and running to the end of the line, e.g. the
+/// `; init counter` after `load $0 #10 ; init counter`
+pub fn comment(marker: &str) -> impl Fn(&str) -> IResult<&str, Token> + '_ {
+    move |input| {
+        context(
+            "comment",
+            delimited(
+                multispace0,
+                value(Token::Comment, pair(tag(marker), not_line_ending)),
+                multispace0,
+            ),
+        )(input)
+    }
+}
+
+/// Skips any number of comments starting with `marker`, along with the whitespace around them
+pub fn skip_comments(marker: &str) -> impl Fn(&str) -> IResult<&str, ()> + '_ {
+    move |input| value((), preceded(multispace0, many0_count(comment(marker))))(input)
+}
+
+/// Characters that already mean something in the grammar, so a comment marker can't start with them.
+/// `#` in particular introduces immediates, e.g. `load $0 #10`.
+const RESERVED_MARKER_STARTS: [char; 7] = ['#', '$', '@', '.', ':', '\'', '"'];
+
+/// Checks that `marker` can be used to start comments, returning why not if it can't
+pub fn validate_comment_marker(marker: &str) -> Result<(), String> {
+    match marker.chars().next() {
+        None => Err("the comment marker can't be empty".to_string()),
+        Some(c) if RESERVED_MARKER_STARTS.contains(&c) => Err(format!(
+            "'{}' can't start a comment because it already means something in the grammar",
+            c
+        )),
+        Some(_)
+            if marker
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_alphanumeric()) =>
+        {
+            Err(format!(
+                "the comment marker '{}' can't contain letters, digits or spaces",
+                marker
+            ))
+        },
+        Some(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{comment, validate_comment_marker};
+    use crate::assembler::Token;
+
+    #[test]
+    fn test_comment() {
+        assert_eq!(
+            comment(";")("  ; init counter\nhlt"),
+            Ok(("hlt", Token::Comment))
+        );
+        assert_eq!(comment(";")(";"), Ok(("", Token::Comment)));
+        assert!(comment(";")("hlt ; stop").is_err());
+        assert_eq!(comment("//")("// stop\n"), Ok(("", Token::Comment)));
+        assert!(comment("//")("; stop").is_err());
+    }
+
+    #[test]
+    fn test_validate_comment_marker() {
+        assert!(validate_comment_marker(";").is_ok());
+        assert!(validate_comment_marker("//").is_ok());
+        assert!(validate_comment_marker("#").is_err());
+        assert!(validate_comment_marker("").is_err());
+        assert!(validate_comment_marker("rem").is_err());
+    }
+}
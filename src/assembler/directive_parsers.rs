@@ -1,16 +1,19 @@
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alpha1, line_ending, multispace0},
+    bytes::complete::{tag, take_while1},
+    character::complete::{line_ending, multispace0},
     combinator::{eof, map, map_res, opt},
     error::context,
+    multi::many1,
     sequence::{preceded, terminated, tuple},
     IResult,
 };
 
 use super::{
-    instruction_parsers::AssemblerInstruction, label_parsers::label_declaration,
-    operand_parser::operand, Token,
+    instruction_parsers::AssemblerInstruction,
+    label_parsers::label_declaration,
+    operand_parser::{integer_operand, operand},
+    Token,
 };
 
 pub fn directive_declaration(input: &str) -> IResult<&str, Token> {
@@ -18,11 +21,17 @@ pub fn directive_declaration(input: &str) -> IResult<&str, Token> {
         "directive_declaration",
         preceded(
             multispace0,
-            map_res(preceded(tag("."), alpha1), |directive| {
-                Ok::<Token, &str>(Token::Directive {
-                    name: String::from(directive),
-                })
-            }),
+            map_res(
+                preceded(
+                    tag("."),
+                    take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+                ),
+                |directive| {
+                    Ok::<Token, &str>(Token::Directive {
+                        name: String::from(directive),
+                    })
+                },
+            ),
         ),
     )(input)
 }
@@ -56,15 +65,54 @@ fn directive_combined(input: &str) -> IResult<&str, AssemblerInstruction> {
     )(input)
 }
 
+/// `.bytes` takes any number of integer operands, so it gets a form of its own:
+/// magic: .bytes #77 #90 #0
+fn directive_bytes(input: &str) -> IResult<&str, AssemblerInstruction> {
+    context(
+        "directive_bytes",
+        preceded(
+            multispace0,
+            terminated(
+                map(
+                    tuple((
+                        opt(label_declaration),
+                        preceded(multispace0, tag(".bytes")),
+                        many1(integer_operand),
+                    )),
+                    |(l, _, values)| AssemblerInstruction {
+                        opcode: None,
+                        directive: Some(Token::Directive {
+                            name: String::from("bytes"),
+                        }),
+                        label: l,
+                        operand1: Some(Token::IntegerList {
+                            values: values
+                                .into_iter()
+                                .filter_map(|t| match t {
+                                    Token::IntegerOperand { value } => Some(value),
+                                    _ => None,
+                                })
+                                .collect(),
+                        }),
+                        operand2: None,
+                        operand3: None,
+                    },
+                ),
+                alt((multispace0, line_ending, eof)),
+            ),
+        ),
+    )(input)
+}
+
 pub fn directive(input: &str) -> IResult<&str, AssemblerInstruction> {
-    context("directive", alt((directive_combined,)))(input)
+    context("directive", alt((directive_bytes, directive_combined)))(input)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::assembler::{instruction_parsers::AssemblerInstruction, Token};
 
-    use super::{directive_combined, directive_declaration};
+    use super::{directive, directive_combined, directive_declaration};
 
     #[test]
     fn test_directive() {
@@ -107,4 +155,18 @@ mod tests {
 
         assert_eq!(directive, correct_instruction);
     }
+
+    #[test]
+    fn test_bytes_directive() {
+        let result = directive("magic: .bytes #77 #90 #0\n");
+        assert_eq!(result.is_ok(), true);
+        let (rest, directive) = result.unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            directive.operand1,
+            Some(Token::IntegerList {
+                values: vec![77, 90, 0]
+            })
+        );
+    }
 }
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use nom::{
     branch::alt,
     character::complete::{line_ending, multispace0},
@@ -8,9 +10,10 @@ use nom::{
 };
 
 use super::{
-    label_parsers::label_declaration, opcode_parsers::opcode, operand_parser::operand, SymbolTable,
-    Token,
+    assembler_errors::AssemblerError, label_parsers::label_declaration, opcode_parsers::opcode,
+    operand_parser::operand, SymbolTable, Token,
 };
+use crate::instruction::{Opcode, OperandLayout};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct AssemblerInstruction {
@@ -32,8 +35,16 @@ impl AssemblerInstruction {
     /// 返回：
     ///     一个包含字节码的向量，表示该 CPU 指令
     pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
-        // 初始化存储字节码的向量
-        let mut results: Vec<u8> = vec![];
+        let mut results = Vec::with_capacity(4);
+        self.to_bytes_into(&mut results, symbols);
+        results
+    }
+
+    /// Same as `to_bytes`, but appends the bytecode to `results`, so a whole program can be
+    /// written into one buffer without allocating per instruction
+    pub fn to_bytes_into(&self, results: &mut Vec<u8>, symbols: &SymbolTable) {
+        // 这条指令的字节码从这里开始, 补齐到 4 字节时以它为准
+        let start = results.len();
 
         // 根据操作码将其转换为字节码
         match self.opcode {
@@ -48,20 +59,80 @@ impl AssemblerInstruction {
             },
         }
 
+        // LOAD32 的立即数放在紧跟着的下一个 4 字节槽里
+        // LOAD32 keeps its immediate in the following 4 byte slot
+        if let (
+            Some(Token::Op {
+                code: Opcode::LOAD32,
+            }),
+            Some(value),
+        ) = (
+            &self.opcode,
+            self.operand2
+                .as_ref()
+                .and_then(|t| AssemblerInstruction::immediate(t, symbols)),
+        ) {
+            if let Some(token) = &self.operand1 {
+                AssemblerInstruction::extract_operand(token, results, symbols);
+            }
+            while results.len() - start < 4 {
+                results.push(0);
+            }
+            results.extend_from_slice(&value.to_be_bytes());
+            return;
+        }
+
         // 遍历指令的操作数，将它们转换为字节码
-        for operand in vec![&self.operand1, &self.operand2, &self.operand3] {
+        for operand in vec![&self.operand1, &self.operand2] {
             if let Some(token) = operand {
                 // 如果操作数存在，调用提取函数将其添加到结果向量中
-                AssemblerInstruction::extract_operand(token, &mut results, symbols);
+                AssemblerInstruction::extract_operand(token, results, symbols);
+            }
+        }
+        if let Some(token) = &self.operand3 {
+            // LOADMO 和 ADDI 的第三个操作数只剩一个字节，按 8 位有符号立即数编码；
+            // 超出范围的值由 `third_immediate_error` 报错
+            // LOADMO and ADDI only have one byte left for their third operand, a signed 8-bit
+            // immediate; `third_immediate_error` reports values that don't fit
+            match (
+                self.third_is_imm8(),
+                AssemblerInstruction::immediate(token, symbols),
+            ) {
+                (true, Some(value)) => results.push(value as i8 as u8),
+                _ => AssemblerInstruction::extract_operand(token, results, symbols),
             }
         }
 
-        while 0 < results.len() && results.len() < 4 {
+        while 0 < results.len() - start && results.len() - start < 4 {
             results.push(0);
         }
+    }
 
-        // 返回包含指令字节码的向量
-        results
+    /// Whether the third operand is the signed 8-bit immediate of LOADMO or ADDI
+    fn third_is_imm8(&self) -> bool {
+        matches!(&self.opcode, Some(Token::Op { code }) if code.operand_layout() == OperandLayout::RegRegImm8)
+    }
+
+    /// Checks an integer (or integer constant) third operand: LOADMO and ADDI need it to fit in
+    /// an i8, every other opcode only takes a register there
+    pub fn third_immediate_error(
+        &self,
+        symbols: &SymbolTable,
+        instruction: u32,
+    ) -> Option<AssemblerError> {
+        let value = AssemblerInstruction::immediate(self.operand3.as_ref()?, symbols)?;
+        if !self.third_is_imm8() {
+            return Some(AssemblerError::UnexpectedImmediate { instruction });
+        }
+        if i8::try_from(value).is_err() {
+            return Some(AssemblerError::ImmediateOutOfRange {
+                instruction,
+                value,
+                min: i8::MIN as i32,
+                max: i8::MAX as i32,
+            });
+        }
+        None
     }
 
     pub fn is_label(&self) -> bool {
@@ -81,6 +152,30 @@ impl AssemblerInstruction {
         self.operand1.is_some() || self.operand2.is_some() || self.operand3.is_some()
     }
 
+    /// The labels this instruction refers to, each with the offset of its 2 bytes within the
+    /// encoded instruction
+    pub fn label_usages(&self) -> Vec<(usize, String)> {
+        let mut usages = vec![];
+        // The opcode takes up the first byte
+        let mut offset = 1;
+        for (index, operand) in [&self.operand1, &self.operand2, &self.operand3]
+            .into_iter()
+            .enumerate()
+        {
+            match operand {
+                Some(Token::Register { .. }) => offset += 1,
+                Some(Token::IntegerOperand { .. }) if index == 2 => offset += 1,
+                Some(Token::IntegerOperand { .. }) => offset += 2,
+                Some(Token::LabelUsage { name }) => {
+                    usages.push((offset, name.clone()));
+                    offset += 2;
+                },
+                _ => {},
+            }
+        }
+        usages
+    }
+
     pub fn get_label_name(&self) -> Option<String> {
         match &self.label {
             Some(Token::LabelDeclaration { name }) => Some(name.to_string()),
@@ -102,6 +197,16 @@ impl AssemblerInstruction {
         }
     }
 
+    /// The value of an immediate operand: either a literal like `#1` or a constant like `@step`
+    /// that was declared with `.integer`
+    fn immediate(t: &Token, symbols: &SymbolTable) -> Option<i32> {
+        match t {
+            Token::IntegerOperand { value } => Some(*value),
+            Token::LabelUsage { name } => symbols.symbol_constant(name),
+            _ => None,
+        }
+    }
+
     /// 从解析令牌中提取操作数并将其转换为字节后存储到结果向量中。
     ///
     /// 该函数根据传入的令牌类型执行不同的操作以提取操作数。
@@ -120,21 +225,19 @@ impl AssemblerInstruction {
             },
             // 对于整数操作数类型的令牌，将其值转换为两个字节后提取并存储。
             Token::IntegerOperand { value } => {
-                let converted = *value as u16;
-                let byte1 = converted;
-                let byte2 = converted >> 8;
-                // 利用大端序规则，将最高有效字节首先存储。
-                // obuse the big endian rule that store the most significant byte first
-                results.push(byte2 as u8);
-                results.push(byte1 as u8);
+                // 和字节码里的其它多字节值一样，按大端序存储，最高有效字节在前。
+                // like every multi-byte value in the bytecode, this is big endian: most significant byte first
+                results.extend_from_slice(&(*value as u16).to_be_bytes());
             },
             Token::LabelUsage { name } => {
-                if let Some(value) = symbols.symbol_value(name) {
-                    let byte1 = value;
-                    let byte2 = value >> 8;
-                    results.push(byte2 as u8);
-                    results.push(byte1 as u8);
-                }
+                // 整数常量写入它的值；没有解析到的标签写成 0，留给链接器（或者之后的回填）去修补
+                // integer constants are written as their value; labels that don't resolve yet
+                // are written as 0, for a linker to patch later
+                let value = match symbols.symbol_constant(name) {
+                    Some(constant) => constant as u32,
+                    None => symbols.symbol_value(name).unwrap_or(0),
+                };
+                results.extend_from_slice(&(value as u16).to_be_bytes());
             },
             // 对于其他所有令牌类型，打印错误信息并退出程序。
             _ => {
@@ -145,6 +248,41 @@ impl AssemblerInstruction {
     }
 }
 
+/// Writes the instruction back out as assembly source, e.g. `loop: load $0 #100`
+impl fmt::Display for AssemblerInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(name) = self.get_label_name() {
+            parts.push(format!("{}:", name));
+        }
+        if let Some(Token::Op { code }) = &self.opcode {
+            parts.push(format!("{:?}", code).to_lowercase());
+        }
+        if let Some(name) = self.get_directive_name() {
+            parts.push(format!(".{}", name));
+        }
+        for operand in [&self.operand1, &self.operand2, &self.operand3]
+            .into_iter()
+            .flatten()
+        {
+            parts.push(match operand {
+                Token::Register { reg_num } => format!("${}", reg_num),
+                Token::IntegerOperand { value } => format!("#{}", value),
+                Token::Float { value } => format!("#{:?}", value),
+                Token::LabelUsage { name } => format!("@{}", name),
+                Token::IrString { name } => format!("'{}'", name),
+                Token::IntegerList { values } => values
+                    .iter()
+                    .map(|v| format!("#{}", v))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                other => format!("{:?}", other),
+            });
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
 fn instruction_combined(input: &str) -> IResult<&str, AssemblerInstruction> {
     context(
         // use context to show better error msg when failed to parse
@@ -1,6 +1,6 @@
 use nom::{
-    bytes::complete::tag,
-    character::complete::{alphanumeric1, char, multispace0},
+    bytes::complete::{tag, take_while1},
+    character::complete::{char, multispace0},
     combinator::{map, map_res, opt},
     error::context,
     sequence::{preceded, tuple},
@@ -9,13 +9,18 @@ use nom::{
 
 use super::Token;
 
+/// Label names are letters, digits and underscores, e.g. `extern_func`
+fn label_name(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '_')(input)
+}
+
 /// Looks for a user-defined label, such as `label1:`
 pub fn label_declaration(input: &str) -> IResult<&str, Token> {
     context(
         "label_declaration",
         preceded(
             multispace0,
-            map_res(tuple((alphanumeric1, tag(":"))), |(lable, _)| {
+            map_res(tuple((label_name, tag(":"))), |(lable, _)| {
                 Ok::<Token, &str>(Token::LabelDeclaration {
                     name: String::from(lable),
                 })
@@ -31,7 +36,7 @@ pub fn label_usage(input: &str) -> IResult<&str, Token> {
         preceded(
             multispace0,
             map(
-                tuple((char('@'), alphanumeric1, opt(multispace0))),
+                tuple((char('@'), label_name, opt(multispace0))),
                 |(_c, name, _)| Token::LabelUsage {
                     name: String::from(name),
                 },
@@ -109,5 +114,16 @@ mod tests {
 
         let result = label_usage(" @  1te12st \n");
         assert_eq!(result.is_ok(), false);
+
+        let result = label_usage("@extern_func\n");
+        assert_eq!(
+            result,
+            Ok((
+                "",
+                Token::LabelUsage {
+                    name: "extern_func".to_string()
+                }
+            ))
+        );
     }
 }
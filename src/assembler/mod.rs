@@ -1,15 +1,19 @@
-use std::vec;
+use std::{fs, mem, path::Path, vec};
 
-use assembler_errors::AssemblerError;
-use byteorder::{LittleEndian, WriteBytesExt};
+use assembler_errors::{AssemblerError, AssemblerWarning};
+use byteorder::{BigEndian, WriteBytesExt};
+use comments::{validate_comment_marker, DEFAULT_COMMENT_MARKER};
 use instruction_parsers::AssemblerInstruction;
 use log::{debug, error, warn};
-use program_parser::{program, Program};
+use nom::IResult;
+use operand_parser::unescape;
+use program_parser::{program_with_comments, Program};
 use symbols::{Symbol, SymbolTable, SymbolType};
 
-use crate::instruction::Opcode;
+use crate::{instruction::Opcode, vm::RegisterAssertion};
 
 pub mod assembler_errors;
+pub mod comments;
 pub mod directive_parsers;
 pub mod instruction_parsers;
 pub mod label_parsers;
@@ -19,12 +23,28 @@ pub mod program_parser;
 pub mod register_parser;
 pub mod symbols;
 
+// Every multi-byte value in the bytecode, header fields and instruction operands alike,
+// is stored big endian (most significant byte first).
+//
+// A program is laid out as the 64 byte header, a 4 byte slot, the read-only section and then
+// the code. The header keeps the length of the read-only section at `PIE_HEADER_RO_LENGTH_OFFSET`,
+// which is what tells the VM where the code starts. The slot after the header repeats it.
+
 /// Magic number that begins every bytecode file prefix. These spell out EPIE in ASCII, if you were wondering.
 pub const PIE_HEADER_PREFIX: [u8; 4] = [45, 50, 49, 45]; // Hello
 
 /// Constant that determines how long the header is. There are 60 zeros left after the prefix, for later usage if needed.
 pub const PIE_HEADER_LENGTH: usize = 64;
 
+/// Where the header keeps the length of the read-only section, as a u32
+pub const PIE_HEADER_RO_LENGTH_OFFSET: usize = 4;
+
+/// Where the header keeps the lowest VM version that can run the program, as a u32
+pub const PIE_HEADER_VERSION_OFFSET: usize = 8;
+
+/// Default upper bound for the read-only section, generous enough for any hand-written program
+pub const DEFAULT_MAX_RO_SIZE: usize = 1024 * 1024;
+
 pub fn prepend_header(mut append_bytes: Vec<u8>) -> Vec<u8> {
     let mut prepension = vec![];
     for byte in PIE_HEADER_PREFIX.into_iter() {
@@ -40,6 +60,24 @@ pub fn prepend_header(mut append_bytes: Vec<u8>) -> Vec<u8> {
     prepension
 }
 
+/// Builds an error pointing at where parsing stopped, `rest` being the unparsed tail of `source`.
+/// Lines and columns count from 1.
+fn syntax_error(source: &str, rest: &str) -> AssemblerError {
+    let parsed = &source[..source.len() - rest.len()];
+    let line = parsed.matches('\n').count() + 1;
+    let column = parsed.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    let token = rest.lines().next().unwrap_or("").trim_end();
+    AssemblerError::SyntaxError {
+        line,
+        column,
+        error: if token.is_empty() {
+            "unexpected end of input".to_string()
+        } else {
+            format!("could not parse `{}`", token)
+        },
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Op { code: Opcode },
@@ -47,6 +85,7 @@ pub enum Token {
     Factor { value: Box<Token> },
     Float { value: f64 },
     IntegerOperand { value: i32 },
+    IntegerList { values: Vec<i32> },
     LabelDeclaration { name: String },
     LabelUsage { name: String },
     Directive { name: String },
@@ -54,7 +93,17 @@ pub enum Token {
     Comment,
 }
 
-#[derive(Debug, Default)]
+/// One instruction of the last assembled program and the bytes it became
+#[derive(Debug, PartialEq, Clone)]
+pub struct ListingEntry {
+    /// Where the instruction starts, counted from the beginning of the code section
+    pub offset: usize,
+    /// The instruction written back out as source
+    pub instruction: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
 pub struct Assembler {
     /// Tracks which phase the assember is in
     phase: AssemblerPhase,
@@ -74,6 +123,28 @@ pub struct Assembler {
     current_instruction: u32,
     /// Any errors we find along the way. At the end, we'll present them to the user.
     pub errors: Vec<AssemblerError>,
+    /// Things that looked wrong but didn't stop the program from assembling
+    pub warnings: Vec<AssemblerWarning>,
+    /// The largest the read-only section may grow, in bytes
+    max_ro_size: usize,
+    /// Register checks declared with `.assert_reg`, for the VM to verify after running the program
+    pub assertions: Vec<RegisterAssertion>,
+    /// Whether labels that aren't declared in this program are left for a linker to resolve
+    relocatable: bool,
+    /// Where the code refers to undeclared labels: (byte offset in the code section, label name)
+    relocations: Vec<(usize, String)>,
+    /// The lowest VM version the program runs on, declared with `.require_version`
+    required_version: u32,
+    /// Starts a comment that runs to the end of the line
+    comment_marker: String,
+    /// Each instruction of the code section with its offset and encoding, filled in the second pass
+    listing: Vec<ListingEntry>,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
 }
 
 impl Assembler {
@@ -85,23 +156,67 @@ impl Assembler {
             bytecode: vec![],
             sections: vec![],
             errors: vec![],
+            warnings: vec![],
             phase: AssemblerPhase::First,
             symbols: SymbolTable::new(),
             current_section: None,
+            max_ro_size: DEFAULT_MAX_RO_SIZE,
+            assertions: vec![],
+            relocatable: false,
+            relocations: vec![],
+            required_version: 0,
+            comment_marker: DEFAULT_COMMENT_MARKER.to_string(),
+            listing: vec![],
         }
     }
 
-    pub fn assemble(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
-        match program(raw) {
-            Ok((_reminder, program)) => {
+    /// Limits how large the read-only section may grow, e.g. when assembling untrusted input
+    pub fn with_max_ro_size(mut self, max_ro_size: usize) -> Self {
+        self.max_ro_size = max_ro_size;
+        self
+    }
+
+    /// Assembles into a relocatable object: every use of a label that isn't declared in the
+    /// program is written as 0 and recorded in `relocations()` for a linker to patch
+    pub fn with_relocatable(mut self, relocatable: bool) -> Self {
+        self.relocatable = relocatable;
+        self
+    }
+
+    /// Uses `marker` instead of `;` to start comments, e.g. `//`. Markers that clash with the
+    /// rest of the grammar, like `#` which starts immediates, are rejected.
+    pub fn with_comment_char(mut self, marker: &str) -> Result<Self, AssemblerError> {
+        if let Err(reason) = validate_comment_marker(marker) {
+            return Err(AssemblerError::InvalidCommentMarker {
+                marker: marker.to_string(),
+                reason,
+            });
+        }
+        self.comment_marker = marker.to_string();
+        Ok(self)
+    }
+
+    /// The uses of undeclared labels found while assembling a relocatable object
+    pub fn relocations(&self) -> &[(usize, String)] {
+        &self.relocations
+    }
+
+    /// Parses `source` without assembling it, skipping comments that start with the configured
+    /// marker. The REPL uses this for instructions it runs straight away.
+    pub fn parse<'a>(&self, source: &'a str) -> IResult<&'a str, Program> {
+        program_with_comments(source, &self.comment_marker)
+    }
+
+    pub fn assemble(&mut self, source: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        match self.parse(source) {
+            Ok((remainder, program)) => {
                 // If there were no parsing errors, we now have a `Vec<AssemblyInstructions>` to process.
-                // `remainder` _should_ be "".
-                // TODO: Add a check for `remainder`, make sure it is "".
-                debug_assert!(
-                    _reminder.is_empty(),
-                    "_reminder should be empty: {:?}",
-                    _reminder
-                ); // Unlike assert, debug_assert! statements are only enabled in non optimized builds by default.
+                // Parsing stops at the first line it can't make sense of, so anything left over means
+                // the rest of the program would be silently dropped.
+                if !remainder.is_empty() {
+                    self.errors.push(syntax_error(source, remainder));
+                    return Err(mem::take(&mut self.errors));
+                }
 
                 // //First get the header so we can smush it into the bytecode letter
                 // let mut assembled_program = self.write_pie_header();
@@ -111,24 +226,41 @@ impl Assembler {
                 self.process_first_phase(&program);
 
                 // If we accumulated any errors in the first pass, return them and don't try to do the second pass
-                if !&self.errors.is_empty() {
-                    // TODO: Can we avoid a clone here?
-                    return Err(self.errors.clone());
+                // The errors are moved out, which leaves `self.errors` empty for the next run
+                if !self.errors.is_empty() {
+                    return Err(mem::take(&mut self.errors));
                 }
 
                 // Make sure that we have at least one data section and one code section
-                if self.sections.len() != 2 {
+                let has_data = self
+                    .sections
+                    .iter()
+                    .any(|s| matches!(s, AssemblerSection::Data { .. }));
+                let has_code = self
+                    .sections
+                    .iter()
+                    .any(|s| matches!(s, AssemblerSection::Code { .. }));
+                if !(has_data && has_code) {
                     eprintln!("Did not find at least two sections.");
                     self.errors.push(AssemblerError::InsufficientSections);
-                    return Err(self.errors.clone());
+                    return Err(mem::take(&mut self.errors));
                 }
 
                 // Run the second pass, which translates opcodes and associated operands into the bytecode
                 let mut body = self.process_second_phase(&program);
+                if !self.errors.is_empty() {
+                    return Err(mem::take(&mut self.errors));
+                }
 
                 // Get the header so we can smush it into the bytecode letter
                 let mut assembled_program = self.write_pie_header();
 
+                // The slot after the header, then the read-only section the code offset skips over
+                assembled_program
+                    .write_u32::<BigEndian>(self.ro.len() as u32)
+                    .unwrap();
+                assembled_program.extend_from_slice(&self.ro);
+
                 // Merge the header with the populated body vector
                 assembled_program.append(&mut body);
                 Ok(assembled_program)
@@ -136,13 +268,87 @@ impl Assembler {
             Err(e) => {
                 // If there were parsing errors, bad syntax, etc, this arm is run
                 eprintln!("There was an error assembling the code: {:?}", e);
-                Err(vec![AssemblerError::ParseError {
-                    error: e.to_string(),
-                }])
+                let error = match e {
+                    nom::Err::Error(e) | nom::Err::Failure(e) => syntax_error(source, e.input),
+                    nom::Err::Incomplete(_) => AssemblerError::ParseError {
+                        error: e.to_string(),
+                    },
+                };
+                Err(vec![error])
             },
         }
     }
 
+    /// The read-only data collected from the last assembled program, e.g. `.asciiz` strings
+    pub fn ro_section(&self) -> &[u8] {
+        &self.ro
+    }
+
+    /// The executable part of `bytecode` as returned by `assemble`, i.e. everything after the
+    /// header, the slot that follows it and the read-only section
+    pub fn code_section<'a>(&self, bytecode: &'a [u8]) -> &'a [u8] {
+        let ro_len =
+            match bytecode.get(PIE_HEADER_RO_LENGTH_OFFSET..PIE_HEADER_RO_LENGTH_OFFSET + 4) {
+                Some(bytes) => u32::from_be_bytes(bytes.try_into().unwrap()) as usize,
+                None => return &[],
+            };
+        bytecode
+            .get(PIE_HEADER_LENGTH + 4 + ro_len..)
+            .unwrap_or(&[])
+    }
+
+    /// Each instruction of the last assembled program with its offset and encoded bytes
+    pub fn listing(&self) -> &[ListingEntry] {
+        &self.listing
+    }
+
+    /// The last assembled program's instructions, one per line, each followed by a comment with
+    /// its offset in the code section and its encoded bytes:
+    /// `load $0 #100 ; 0000: 00 00 00 64`
+    pub fn annotated_listing(&self) -> String {
+        let width = self
+            .listing
+            .iter()
+            .map(|entry| entry.instruction.len())
+            .max()
+            .unwrap_or(0);
+        let mut text = String::new();
+        for entry in &self.listing {
+            let bytes: Vec<String> = entry.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            text.push_str(&format!(
+                "{:<width$} {} {:04x}: {}\n",
+                entry.instruction,
+                self.comment_marker,
+                entry.offset,
+                bytes.join(" "),
+                width = width
+            ));
+        }
+        text
+    }
+
+    /// Assembles several source files as one program. The sources are concatenated in the given
+    /// order, so they share a single symbol table and labels are visible across files.
+    pub fn assemble_files(&mut self, paths: &[&Path]) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        let mut source = String::new();
+        for path in paths {
+            match fs::read_to_string(path) {
+                Ok(contents) => {
+                    source.push_str(&contents);
+                    source.push('\n');
+                },
+                Err(e) => self.errors.push(AssemblerError::FileReadError {
+                    path: path.display().to_string(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+        if !self.errors.is_empty() {
+            return Err(mem::take(&mut self.errors));
+        }
+        self.assemble(&source)
+    }
+
     fn write_pie_header(&self) -> Vec<u8> {
         let mut header = vec![];
         for byte in PIE_HEADER_PREFIX.into_iter() {
@@ -154,11 +360,16 @@ impl Assembler {
         //First we declare an empty vector for byteorder to write to
         let mut wtr: Vec<u8> = vec![];
 
-        wtr.write_u32::<LittleEndian>(self.ro.len() as u32).unwrap();
+        wtr.write_u32::<BigEndian>(self.ro.len() as u32).unwrap();
 
         // Append those 4 bytes to the header directly after the first four bytes
         header.append(&mut wtr);
 
+        // Followed by the VM version the program needs, so older VMs can refuse to run it
+        header
+            .write_u32::<BigEndian>(self.required_version)
+            .unwrap();
+
         // Now pad the rest of the bytecode header
         while header.len() < PIE_HEADER_LENGTH {
             header.push(0 as u8);
@@ -171,6 +382,9 @@ impl Assembler {
     /// Runs the first pass of the two-pass assembling process.
     /// It looks for labels and puts them in the symbol table
     fn process_first_phase(&mut self, p: &Program) {
+        // Where each instruction starts within the code section, and the code labels found so far
+        let mut code_offset: u32 = 0;
+        let mut code_labels = vec![];
         // Iterate over every instruction, even though in the first phase we only care about labels and directives
         for i in &p.instructions {
             if i.is_label() {
@@ -178,20 +392,40 @@ impl Assembler {
                 if self.current_section.is_some() {
                     // If we have hit a segment header already (e.g., `.code`) then we are ok
                     self.process_label_declaration(&i);
+                    if i.is_opcode() {
+                        if let Some(name) = i.get_label_name() {
+                            code_labels.push((name, code_offset));
+                        }
+                    }
                 } else {
                     // If we have *not* hit a segment header yet, then we have a label outside of a segment, which is not allowed
                     self.errors.push(AssemblerError::NoSegmentDeclarationFound {
                         instruction: self.current_instruction,
                     });
                 }
+            } else if i.is_opcode() && self.current_section.is_none() {
+                // Same goes for a bare instruction that shows up before any segment header
+                self.errors.push(AssemblerError::NoSegmentDeclarationFound {
+                    instruction: self.current_instruction,
+                });
             }
 
             if i.is_directive() {
                 self.process_directive(i);
             }
+            if let Some(Token::Op { code }) = &i.opcode {
+                code_offset += code.instruction_len() as u32;
+            }
             // This is used to keep track of which instruction we hit an error on
             self.current_instruction += 1;
         }
+
+        // The code comes after the header, the slot and the read-only section, whose size is only
+        // known now that every constant has been seen. Code labels become absolute pc values.
+        let code_start = (PIE_HEADER_LENGTH + 4 + self.ro.len()) as u32;
+        for (name, offset) in code_labels {
+            self.symbols.set_symbol_offset(&name, code_start + offset);
+        }
         self.phase = AssemblerPhase::Second;
     }
 
@@ -201,13 +435,32 @@ impl Assembler {
         self.current_instruction = 0;
         // 我们将把要执行的字节码放在一个单独的 Vec 中，这样我们就可以做一些后处理，然后将其与头部和只读部分合并
         // 例子可以是优化，额外检查，等等
-        let mut program = vec![];
+        let mut program = Vec::with_capacity(p.instructions.len() * 4);
+        self.listing.clear();
 
         for i in &p.instructions {
             if i.is_opcode() {
-                // 操作码知道如何正确地将自己转换为 32 位，所以我们可以直接调用 `to_bytes` 并追加到我们的程序中
-                let mut bytes = i.to_bytes(&self.symbols);
-                program.append(&mut bytes);
+                if self.relocatable {
+                    // 记录引用了外部标签的位置，链接时再回填
+                    for (offset, name) in i.label_usages() {
+                        if !self.symbols.has_symbol(&name) {
+                            self.relocations.push((program.len() + offset, name));
+                        }
+                    }
+                }
+                if let Some(error) =
+                    i.third_immediate_error(&self.symbols, self.current_instruction)
+                {
+                    self.errors.push(error);
+                }
+                // 操作码知道如何正确地将自己转换为 32 位，所以我们可以直接调用 `to_bytes_into` 写进我们的程序中
+                let offset = program.len();
+                i.to_bytes_into(&mut program, &self.symbols);
+                self.listing.push(ListingEntry {
+                    offset,
+                    instruction: i.to_string(),
+                    bytes: program[offset..].to_vec(),
+                });
             }
 
             if i.is_directive() {
@@ -270,18 +523,36 @@ impl Assembler {
                 "asciiz" => {
                     self.handle_asciiz(i);
                 },
+                "assert_reg" => {
+                    self.handle_assert_reg(i);
+                },
+                "bytes" => {
+                    self.handle_bytes(i);
+                },
+                "integer" => {
+                    self.handle_integer(i);
+                },
+                "require_version" => {
+                    self.handle_require_version(i);
+                },
+                "space" => {
+                    self.handle_space(i);
+                },
                 _ => {
                     self.errors.push(AssemblerError::UnknownDirectiveFound {
                         directive: directive_name.clone(),
                     });
                 },
             }
+        } else if directive_name == "space" {
+            // A `.space` without a size is a mistake, not a section header
+            self.handle_space(i);
         } else {
             self.process_section_header(&directive_name);
         }
     }
 
-    /// Handles a declaration of a null-terminated string:
+    /// Handles a declaration of a null-terminated string, decoding escapes like `\n` on the way:
     /// hello: .asciiz 'Hello!'
     fn handle_asciiz(&mut self, i: &AssemblerInstruction) {
         // Being a constant declaration, this is only meaningful in the first pass
@@ -298,13 +569,27 @@ impl Assembler {
                     None => {
                         // This would be someone typing:
                         // .asciiz 'Hello'
-                        warn!("Found a string constant with no associated label!");
+                        self.warn(AssemblerWarning::StringConstantWithoutLabel {
+                            instruction: self.current_instruction,
+                        });
+                        return;
+                    },
+                };
+                let bytes = match unescape(&s) {
+                    Ok(bytes) => bytes,
+                    Err(sequence) => {
+                        self.errors
+                            .push(AssemblerError::InvalidEscapeSequence { sequence });
                         return;
                     },
                 };
+                // The string plus its null terminator has to fit in what's left of the section
+                if !self.has_ro_space(bytes.len() + 1) {
+                    return;
+                }
                 // We'll read the string into the read-only section byte-by-byte
-                for b in s.as_bytes() {
-                    self.ro.push(*b);
+                for b in bytes {
+                    self.ro.push(b);
                     self.ro_offset += 1;
                 }
                 // This is the null termination bit we are using to indicate a string has ended
@@ -314,7 +599,179 @@ impl Assembler {
 
             None => {
                 // This just means someone typed `.asciiz` for some reason
-                warn!("String constant following an .asciiz was empty");
+                self.warn(AssemblerWarning::EmptyStringConstant {
+                    instruction: self.current_instruction,
+                });
+            },
+        }
+    }
+
+    /// Records a warning, which is also logged so it isn't lost on callers that ignore `warnings`
+    fn warn(&mut self, warning: AssemblerWarning) {
+        warn!("{}", warning);
+        self.warnings.push(warning);
+    }
+
+    /// Checks that `len` more bytes fit in the read-only section, recording an error if they don't
+    fn has_ro_space(&mut self, len: usize) -> bool {
+        let size = self.ro.len() + len;
+        if size > self.max_ro_size {
+            self.errors.push(AssemblerError::RoSectionTooLarge {
+                size,
+                max: self.max_ro_size,
+            });
+            return false;
+        }
+        true
+    }
+
+    /// Handles a declaration of raw bytes:
+    /// magic: .bytes #77 #90 #0
+    fn handle_bytes(&mut self, i: &AssemblerInstruction) {
+        // Being a constant declaration, this is only meaningful in the first pass
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        let values = match &i.operand1 {
+            Some(Token::IntegerList { values }) => values,
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperands {
+                    directive: "bytes".to_string(),
+                });
+                return;
+            },
+        };
+        let bytes: Vec<u8> = match values.iter().map(|v| u8::try_from(*v)).collect() {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperands {
+                    directive: "bytes".to_string(),
+                });
+                return;
+            },
+        };
+
+        match i.get_label_name() {
+            Some(name) => {
+                self.symbols.set_symbol_offset(&name, self.ro_offset);
+            },
+            None => {
+                self.warn(AssemblerWarning::BytesWithoutLabel {
+                    instruction: self.current_instruction,
+                });
+                return;
+            },
+        };
+        if !self.has_ro_space(bytes.len()) {
+            return;
+        }
+        self.ro_offset += bytes.len() as u32;
+        self.ro.extend(bytes);
+    }
+
+    /// Handles a reservation of zeroed bytes that can be addressed by label:
+    /// buffer: .space #64
+    fn handle_space(&mut self, i: &AssemblerInstruction) {
+        // Being a constant declaration, this is only meaningful in the first pass
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        let len = match &i.operand1 {
+            Some(Token::IntegerOperand { value }) if *value >= 0 => *value as usize,
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperands {
+                    directive: "space".to_string(),
+                });
+                return;
+            },
+        };
+
+        match i.get_label_name() {
+            Some(name) => {
+                self.symbols.set_symbol_offset(&name, self.ro_offset);
+            },
+            None => {
+                self.warn(AssemblerWarning::SpaceWithoutLabel {
+                    instruction: self.current_instruction,
+                });
+                return;
+            },
+        };
+        if !self.has_ro_space(len) {
+            return;
+        }
+        self.ro_offset += len as u32;
+        self.ro.resize(self.ro.len() + len, 0);
+    }
+
+    /// Handles a declaration of a named integer constant, usable as an immediate with `@name`:
+    /// step: .integer #1
+    /// The value is also stored in the read-only section as a big endian word, the same layout
+    /// `loadmo` reads, so `copyro` can bring it onto the heap.
+    fn handle_integer(&mut self, i: &AssemblerInstruction) {
+        // Being a constant declaration, this is only meaningful in the first pass
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        match (i.get_label_name(), &i.operand1) {
+            (Some(name), Some(Token::IntegerOperand { value })) => {
+                self.symbols.set_symbol_constant(&name, *value);
+                self.symbols.set_symbol_offset(&name, self.ro_offset);
+                if !self.has_ro_space(4) {
+                    return;
+                }
+                self.ro.extend_from_slice(&value.to_be_bytes());
+                self.ro_offset += 4;
+            },
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperands {
+                    directive: "integer".to_string(),
+                });
+            },
+        }
+    }
+
+    /// Handles a self-check that the VM verifies once the program has run:
+    /// .assert_reg $2 #15
+    fn handle_assert_reg(&mut self, i: &AssemblerInstruction) {
+        // It doesn't emit any bytes, so there is nothing to do in the second pass
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        match (&i.operand1, &i.operand2) {
+            (Some(Token::Register { reg_num }), Some(Token::IntegerOperand { value })) => {
+                self.assertions.push(RegisterAssertion {
+                    register: *reg_num,
+                    expected: *value,
+                });
+            },
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperands {
+                    directive: "assert_reg".to_string(),
+                });
+            },
+        }
+    }
+
+    /// Handles a declaration of the lowest VM version the program runs on:
+    /// .require_version #1
+    fn handle_require_version(&mut self, i: &AssemblerInstruction) {
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        match &i.operand1 {
+            Some(Token::IntegerOperand { value }) if *value >= 0 => {
+                self.required_version = *value as u32;
+            },
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperands {
+                    directive: "require_version".to_string(),
+                });
             },
         }
     }
@@ -323,10 +780,9 @@ impl Assembler {
         let new_section = AssemblerSection::from(header_name);
         // Only specific section names are allowed
         if new_section == AssemblerSection::Unknown {
-            warn!(
-                "Found an section header that is unknown: {:#?}",
-                header_name
-            );
+            self.warn(AssemblerWarning::UnknownSectionHeader {
+                name: header_name.to_string(),
+            });
             return;
         }
 
@@ -383,10 +839,13 @@ mod tests {
             program_parser::program,
             symbols::{Symbol, SymbolTable, SymbolType},
         },
-        vm::VM,
+        vm::{VM, VM_VERSION},
     };
 
-    use super::Assembler;
+    use super::{
+        assembler_errors::{AssemblerError, AssemblerWarning},
+        Assembler, PIE_HEADER_LENGTH,
+    };
 
     #[test]
     fn test_symbol_table() {
@@ -409,9 +868,9 @@ mod tests {
         let test_string = ".data\n.code\nload $0 #100\nload $1 #1\nload $2 #0\ntest: inc $0\nneq $0 $2\njmpe @test\nhlt";
         let program = asm.assemble(test_string).unwrap();
         let mut vm = VM::new();
-        assert_eq!(program.len(), 92, "\nProgram: {:?}\n", program);
+        assert_eq!(program.len(), 96, "\nProgram: {:?}\n", program);
         vm.add_bytes(program);
-        assert_eq!(vm.program.len(), 92);
+        assert_eq!(vm.program.len(), 96);
     }
 
     #[test]
@@ -432,6 +891,20 @@ mod tests {
         assert_eq!(program.is_ok(), false);
     }
 
+    #[test]
+    /// Tests that the errors are handed back rather than kept in the assembler
+    fn test_assemble_moves_errors_out() {
+        let mut asm = Assembler::new();
+        let errors = asm.assemble(".data\n.code\na: hlt\na: hlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], AssemblerError::SymbolAlreadyDeclared));
+        assert!(asm.errors.is_empty());
+
+        let errors = Assembler::new().assemble(".code\nhlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], AssemblerError::InsufficientSections));
+    }
+
     #[test]
     /// Tests that code which does not declare a segment first does not work
     fn test_first_phase_no_segment() {
@@ -444,6 +917,391 @@ mod tests {
         assert_eq!(asm.errors.len(), 1);
     }
 
+    #[test]
+    /// Tests that `.bytes` puts the raw values in the read-only section under its label
+    fn test_bytes_directive() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\nmagic: .bytes #77 #90 #0\nafter: .asciiz 'x'\n.code\nhlt";
+        assert_eq!(asm.assemble(test_string).is_ok(), true);
+        assert_eq!(asm.ro, vec![77, 90, 0, b'x', 0]);
+        assert_eq!(asm.symbols.symbol_value("magic"), Some(0));
+        assert_eq!(asm.symbols.symbol_value("after"), Some(3));
+
+        let mut asm = Assembler::new();
+        let test_string = ".data\nmagic: .bytes #77 #256\n.code\nhlt";
+        assert!(matches!(
+            asm.assemble(test_string).unwrap_err()[0],
+            AssemblerError::InvalidDirectiveOperands { .. }
+        ));
+    }
+
+    #[test]
+    /// Tests that labels in the code section resolve to the pc of the instruction they mark
+    fn test_code_label_offsets() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\nhi: .asciiz 'hi'\n.code\nstart: load $0 @end\njmp $0\nload32 $2 #70000\nload $1 @start\nend: hlt";
+        let program = asm.assemble(test_string).unwrap();
+        // The code starts after the header, the slot and the 3 bytes of "hi"
+        assert_eq!(asm.symbols.symbol_value("start"), Some(71));
+        assert_eq!(asm.symbols.symbol_value("end"), Some(91));
+        let code = asm.code_section(&program);
+        // Forward to `end` and back to `start`, both big endian
+        assert_eq!(code[0..4], [0, 0, 0, 91]);
+        assert_eq!(code[16..20], [0, 1, 0, 71]);
+
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        assert!(vm.run_summary().is_success());
+        assert_eq!(vm.registers[2], 0);
+    }
+
+    #[test]
+    /// Tests that the annotated listing pairs each instruction with its offset and bytes
+    fn test_annotated_listing() {
+        let mut asm = Assembler::new();
+        asm.assemble(".data\n.code\nload $0 #100\nstart: add $0 $1 $2\n")
+            .unwrap();
+        assert_eq!(
+            asm.annotated_listing(),
+            "load $0 #100        ; 0000: 00 00 00 64\nstart: add $0 $1 $2 ; 0004: 01 00 01 02\n"
+        );
+
+        // The listing is itself valid source
+        let source = format!(".data\n.code\n{}", asm.annotated_listing());
+        let mut again = Assembler::new();
+        again.assemble(&source).unwrap();
+        assert_eq!(again.listing(), asm.listing());
+    }
+
+    #[test]
+    /// Tests that comments are skipped, using `;` unless another marker is configured
+    fn test_comment_char() {
+        let expected = Assembler::new().assemble(".data\n.code\nhlt\n").unwrap();
+
+        let mut asm = Assembler::new();
+        let program = asm.assemble("; foo\n.data\n.code\nhlt ; stop\n").unwrap();
+        assert_eq!(program, expected);
+
+        let mut asm = Assembler::new().with_comment_char("//").unwrap();
+        let program = asm.assemble("// foo\n.data\n.code\nhlt // stop\n").unwrap();
+        assert_eq!(program, expected);
+        // `;` is an ordinary character once another marker is configured
+        let mut asm = Assembler::new().with_comment_char("//").unwrap();
+        assert!(asm.assemble(".data\n.code\nhlt ; stop\n").is_err());
+
+        assert!(matches!(
+            Assembler::new().with_comment_char("#"),
+            Err(AssemblerError::InvalidCommentMarker { .. })
+        ));
+    }
+
+    #[test]
+    /// Tests that a third-operand immediate is range checked instead of wrapped
+    fn test_third_operand_immediate() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nloadmo $0 $1 #-128\naddi $0 $0 #127\n")
+            .unwrap();
+        assert_eq!(asm.code_section(&program), [54, 0, 1, 128, 67, 0, 0, 127]);
+
+        for source in [
+            ".data\n.code\nloadmo $0 $1 #200\n",
+            ".data\n.code\nloadmo $0 $1 #-129\n",
+            ".data\nbig: .integer #300\n.code\naddi $0 $0 @big\n",
+        ] {
+            let errors = Assembler::new().assemble(source).unwrap_err();
+            assert!(
+                matches!(
+                    errors[0],
+                    AssemblerError::ImmediateOutOfRange {
+                        min: -128,
+                        max: 127,
+                        ..
+                    }
+                ),
+                "{}: {:?}",
+                source,
+                errors
+            );
+        }
+
+        let errors = Assembler::new()
+            .assemble(".data\n.code\nhlt\nadd $0 $1 #300\n")
+            .unwrap_err();
+        assert!(matches!(
+            errors[0],
+            AssemblerError::UnexpectedImmediate { instruction: 3 }
+        ));
+    }
+
+    #[test]
+    /// Tests that an `.integer` constant can stand in for an immediate
+    fn test_integer_constant_immediate() {
+        let mut asm = Assembler::new();
+        let named = asm
+            .assemble(".data\nstep: .integer #1\nbig: .integer #300\n.code\naddi $0 $0 @step\nload $1 @big\n")
+            .unwrap();
+        let mut asm = Assembler::new();
+        let literal = asm
+            .assemble(".data\n.code\naddi $0 $0 #1\nload $1 #300\n")
+            .unwrap();
+        assert_eq!(asm.code_section(&literal), [67, 0, 0, 1, 0, 1, 1, 44]);
+        assert_eq!(asm.code_section(&named), asm.code_section(&literal));
+
+        let mut asm = Assembler::new();
+        assert!(matches!(
+            asm.assemble(".data\nstep: .integer 'one'\n.code\nhlt")
+                .unwrap_err()[0],
+            AssemblerError::InvalidDirectiveOperands { .. }
+        ));
+    }
+
+    #[test]
+    /// Tests that a parse error reports the line and column it happened on
+    fn test_syntax_error_location() {
+        let mut asm = Assembler::new();
+        let errors = asm
+            .assemble("; counter program\n; starts here\n  lod $0 #1\nhlt")
+            .unwrap_err();
+        match &errors[0] {
+            AssemblerError::SyntaxError {
+                line,
+                column,
+                error,
+            } => {
+                assert_eq!(*line, 3);
+                assert_eq!(*column, 3);
+                assert_eq!(error, "could not parse `lod $0 #1`");
+            },
+            e => panic!("expected a syntax error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    /// Tests that input after the last instruction that parses is an error, not silently dropped
+    fn test_leftover_input() {
+        let mut asm = Assembler::new();
+        let errors = asm
+            .assemble(".data\n.code\nload $0 #1\nhlt\n%% garbage\nload $1 #2")
+            .unwrap_err();
+        match &errors[0] {
+            AssemblerError::SyntaxError { line, error, .. } => {
+                assert_eq!(*line, 5);
+                assert_eq!(error, "could not parse `%% garbage`");
+            },
+            e => panic!("expected a syntax error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    /// Tests that `.space` reserves zeroed bytes behind its label
+    fn test_space() {
+        let mut asm = Assembler::new();
+        asm.assemble(".data\nhi: .asciiz 'hi'\nbuffer: .space #64\nafter: .bytes #7\n.code\nhlt")
+            .unwrap();
+        assert_eq!(asm.ro_section().len(), 3 + 64 + 1);
+        assert!(asm.ro_section()[3..67].iter().all(|b| *b == 0));
+        assert_eq!(asm.symbols.symbol_value("buffer"), Some(3));
+        assert_eq!(asm.symbols.symbol_value("after"), Some(67));
+
+        for source in [
+            ".data\nbuffer: .space\n.code\nhlt",
+            ".data\nbuffer: .space #-1\n.code\nhlt",
+        ] {
+            let mut asm = Assembler::new();
+            assert!(matches!(
+                asm.assemble(source).unwrap_err()[0],
+                AssemblerError::InvalidDirectiveOperands { .. }
+            ));
+        }
+    }
+
+    #[test]
+    /// Tests that escapes in `.asciiz` strings are decoded into the read-only section
+    fn test_asciiz_escapes() {
+        let mut asm = Assembler::new();
+        asm.assemble(".data\nmsg: .asciiz 'line1\\nline2'\n.code\nhlt")
+            .unwrap();
+        assert_eq!(asm.ro_section(), b"line1\nline2\0");
+        assert_eq!(asm.ro_section()[5], 0x0A);
+
+        let mut asm = Assembler::new();
+        assert!(matches!(
+            asm.assemble(".data\nmsg: .asciiz 'bad\\q'\n.code\nhlt")
+                .unwrap_err()[0],
+            AssemblerError::InvalidEscapeSequence { .. }
+        ));
+    }
+
+    #[test]
+    /// Tests that `.integer` stores its value in the read-only section
+    fn test_integer_ro_data() {
+        let mut asm = Assembler::new();
+        asm.assemble(
+            ".data\nhi: .asciiz 'hi'\nanswer: .integer #42\nneg: .integer #-2\n.code\nhlt",
+        )
+        .unwrap();
+        assert_eq!(
+            asm.ro_section(),
+            [104, 105, 0, 0, 0, 0, 42, 255, 255, 255, 254]
+        );
+        assert_eq!(asm.symbols.symbol_value("answer"), Some(3));
+        assert_eq!(asm.symbols.symbol_value("neg"), Some(7));
+
+        let mut asm = Assembler::new();
+        assert!(matches!(
+            asm.assemble(".data\n.integer #42\n.code\nhlt").unwrap_err()[0],
+            AssemblerError::InvalidDirectiveOperands { .. }
+        ));
+    }
+
+    #[test]
+    /// Tests that uses of undeclared labels become relocations in a relocatable object
+    fn test_relocations() {
+        let test_string = ".data\n.code\nload $0 #1\njmpe @extern_func\nhlt";
+        let mut asm = Assembler::new().with_relocatable(true);
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.relocations(), [(5, "extern_func".to_string())]);
+        assert_eq!(asm.code_section(&program)[4..8], [15, 0, 0, 0]);
+
+        let mut asm = Assembler::new();
+        assert!(asm.assemble(test_string).is_ok());
+        assert!(asm.relocations().is_empty());
+    }
+
+    #[test]
+    /// Tests that the read-only data and the code can be looked at separately
+    fn test_sections() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\nhello: .asciiz 'Hi'\n.code\nhlt";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.ro_section(), b"Hi\0");
+        assert_eq!(asm.code_section(&program), [5, 0, 0, 0]);
+        assert!(asm.code_section(&[]).is_empty());
+    }
+
+    #[test]
+    /// Tests that a label declared in one file can be used from another
+    fn test_assemble_files() {
+        let dir = std::env::temp_dir();
+        let code = dir.join(format!("lrvm_asm_code_{}.iasm", std::process::id()));
+        let data = dir.join(format!("lrvm_asm_data_{}.iasm", std::process::id()));
+        std::fs::write(&code, ".code\nprts @greeting\nhlt\n").unwrap();
+        std::fs::write(&data, ".data\npad: .asciiz 'Hi'\ngreeting: .asciiz 'Yo'\n").unwrap();
+
+        let mut asm = Assembler::new();
+        let program = asm.assemble_files(&[code.as_path(), data.as_path()]);
+        std::fs::remove_file(&code).unwrap();
+        std::fs::remove_file(&data).unwrap();
+
+        let program = program.unwrap();
+        assert_eq!(asm.symbols.symbol_value("greeting"), Some(3));
+        assert_eq!(asm.code_section(&program), [21, 0, 3, 0, 5, 0, 0, 0]);
+
+        let mut asm = Assembler::new();
+        assert!(matches!(
+            asm.assemble_files(&[std::path::Path::new("does/not/exist.iasm")])
+                .unwrap_err()[0],
+            AssemblerError::FileReadError { .. }
+        ));
+    }
+
+    #[test]
+    /// Tests that what the assembler encodes is what the VM decodes
+    fn test_assembled_values_round_trip() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\n.code\nload $0 #500\nload32 $1 #305419896\nhlt";
+        let program = asm.assemble(test_string).unwrap();
+
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 500);
+        assert_eq!(vm.registers[1], 0x12345678);
+    }
+
+    #[test]
+    /// Tests that the VM refuses programs that need a newer version of it
+    fn test_require_version() {
+        let mut asm = Assembler::new();
+        let test_string = format!(
+            ".data\n.code\n.require_version #{}\nload $0 #7\nhlt",
+            VM_VERSION + 1
+        );
+        let program = asm.assemble(&test_string).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 0);
+
+        let mut asm = Assembler::new();
+        let test_string = format!(
+            ".data\n.code\n.require_version #{}\nload $0 #7\nhlt",
+            VM_VERSION
+        );
+        let program = asm.assemble(&test_string).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 7);
+    }
+
+    #[test]
+    /// Tests that a warning doesn't stop the program from assembling but is still reported
+    fn test_warnings_do_not_fail_assembly() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\n.asciiz 'Hello'\n.code\nhlt";
+        assert!(asm.assemble(test_string).is_ok());
+        assert!(asm.errors.is_empty());
+        assert_eq!(
+            asm.warnings,
+            vec![AssemblerWarning::StringConstantWithoutLabel { instruction: 1 }]
+        );
+    }
+
+    #[test]
+    /// Tests that `.assert_reg` checks are recorded and verified by the VM after running
+    fn test_assert_reg() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\n.code\nload $0 #10\nload $1 #5\nadd $0 $1 $2\nhlt\n.assert_reg $2 #15\n.assert_reg $0 #11";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.assertions.len(), 2);
+
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.add_assertions(asm.assertions.clone());
+        vm.run();
+        let failures = vm.check_assertions();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, asm.assertions[1]);
+        assert_eq!(failures[0].1, 10);
+    }
+
+    #[test]
+    /// Tests that `.assert_reg` needs a register and an integer
+    fn test_assert_reg_bad_operands() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\n.code\nhlt\n.assert_reg #15";
+        let result = asm.assemble(test_string);
+        assert!(matches!(
+            result.unwrap_err()[0],
+            AssemblerError::InvalidDirectiveOperands { .. }
+        ));
+    }
+
+    #[test]
+    /// Tests that an instruction which comes before any segment declaration does not work
+    fn test_first_phase_instruction_before_segment() {
+        let mut asm = Assembler::new();
+        let test_string = "load $0 #1\n.code\nhlt";
+        let result = asm.assemble(test_string);
+        assert_eq!(result.is_ok(), false);
+        assert!(matches!(
+            result.unwrap_err()[0],
+            AssemblerError::NoSegmentDeclarationFound { instruction: 0 }
+        ));
+    }
+
     #[test]
     /// Tests that code inside a proper segment works
     fn test_first_phase_inside_segment() {
@@ -465,6 +1323,43 @@ mod tests {
         assert_eq!(program.is_ok(), true);
 
         let program = program.unwrap();
-        assert_eq!(program[4], 6);
+        assert_eq!(program[4..8], [0, 0, 0, 6]);
+    }
+
+    #[test]
+    /// Tests that a program with read-only data starts executing right after it
+    fn test_ro_data_round_trip() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\ngreeting: .asciiz 'Hello'\n.code\nload $0 #100\nhlt";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(
+            program[PIE_HEADER_LENGTH + 4..PIE_HEADER_LENGTH + 10],
+            *b"Hello\0"
+        );
+        assert_eq!(asm.code_section(&program), [0, 0, 0, 100, 5, 0, 0, 0]);
+
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        let result = vm.run_summary();
+        assert!(result.is_success());
+        assert_eq!(vm.registers[0], 100);
+        assert_eq!(vm.instruction_count(), 2);
+    }
+
+    #[test]
+    /// Tests that string constants can't grow the read-only section past the configured limit
+    fn test_max_ro_size() {
+        let mut asm = Assembler::new().with_max_ro_size(8);
+        let test_string = ".data\ntest: .asciiz 'Hello'\n.code\nhlt";
+        assert_eq!(asm.assemble(test_string).is_ok(), true);
+
+        let mut asm = Assembler::new().with_max_ro_size(8);
+        let test_string = ".data\ntest: .asciiz 'Hello World'\n.code\nhlt";
+        let result = asm.assemble(test_string);
+        assert_eq!(result.is_ok(), false);
+        assert!(matches!(
+            result.unwrap_err()[0],
+            AssemblerError::RoSectionTooLarge { size: 12, max: 8 }
+        ));
     }
 }
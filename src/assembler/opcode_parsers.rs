@@ -1,10 +1,10 @@
 use crate::assembler::Token;
 use crate::instruction::Opcode;
 use nom::{
-    character::complete::{alpha1, multispace0},
-    combinator::map_res,
+    character::complete::{alpha1, alphanumeric0, multispace0},
+    combinator::{map_opt, recognize},
     error::context,
-    sequence::preceded,
+    sequence::{pair, preceded},
     IResult,
 };
 
@@ -18,8 +18,8 @@ use nom::{
 ///
 /// # 描述
 /// 该函数使用`context`组合器设置错误上下文为"opcode"，并尝试使用`alt`组合器的备选方案解析输入字符串
-/// 如果输入字符串以数字或字母开头，则使用`map_res`组合器映射结果为`Token::Op`变体，其中`code`字段是通过调用`opcode_from_str`
-/// 函数从字符串转换得到的
+/// 如果输入字符串以数字或字母开头，则使用`map_opt`组合器映射结果为`Token::Op`变体，其中`code`字段是通过调用`Opcode::try_from`
+/// 函数从字符串转换得到的；不认识的助记符（例如 `lod`）会直接解析失败
 ///
 /// # 例子
 /// ```
@@ -33,10 +33,12 @@ pub fn opcode(input: &str) -> IResult<&str, Token> {
         "opcode",
         preceded(
             multispace0,
-            map_res(alpha1, |s: &str| {
-                Ok::<Token, &str>(Token::Op {
-                    code: Opcode::from(s.to_lowercase().as_str()),
-                })
+            // Mnemonics start with a letter but may carry digits, e.g. `loadf64`
+            // Unknown mnemonics fail here rather than assembling into IGL
+            map_opt(recognize(pair(alpha1, alphanumeric0)), |s: &str| {
+                Opcode::try_from(s.to_lowercase().as_str())
+                    .ok()
+                    .map(|code| Token::Op { code })
             }),
         ),
     )(input)
@@ -68,9 +70,40 @@ mod tests {
 
         // Tests that an invalid opcode isn't recongized
         let result = opcode("aold");
-        assert_eq!(result.is_ok(), true);
-        let (_, token) = result.unwrap();
-        assert_eq!(token, Token::Op { code: Opcode::IGL });
+        assert_eq!(result.is_ok(), false);
+    }
+
+    #[test]
+    fn test_opcode_typo() {
+        assert_eq!(opcode("lod $0 #1").is_ok(), false);
+        assert_eq!(
+            opcode("load $0 #1"),
+            Ok((" $0 #1", Token::Op { code: Opcode::LOAD }))
+        );
+    }
+
+    #[test]
+    fn test_opcode_mod() {
+        assert_eq!(
+            opcode("mod $0 $1 $2"),
+            Ok((" $0 $1 $2", Token::Op { code: Opcode::MOD }))
+        );
+    }
+
+    #[test]
+    fn test_opcode_mov() {
+        assert_eq!(
+            opcode("mov $1 $0"),
+            Ok((" $1 $0", Token::Op { code: Opcode::MOV }))
+        );
+    }
+
+    #[test]
+    fn test_opcode_prti() {
+        assert_eq!(
+            opcode("prti $0"),
+            Ok((" $0", Token::Op { code: Opcode::PRTI }))
+        );
     }
 
     #[test]
@@ -2,11 +2,12 @@ use crate::assembler::Token;
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while},
-    character::complete::{char, digit1, line_ending, multispace0},
-    combinator::{eof, map_res, opt},
+    bytes::complete::{tag, tag_no_case, take_while1},
+    character::complete::{anychar, char, digit1, hex_digit1, line_ending, multispace0, satisfy},
+    combinator::{eof, map_res, not, opt, recognize},
     error::context,
-    sequence::{delimited, preceded, terminated, tuple},
+    multi::many0_count,
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 
@@ -15,7 +16,9 @@ use super::{label_parsers::label_usage, register_parser::register};
 /// Parses an integer operand from a string.
 ///
 /// This function expects the input string to contain an integer operand prefixed by a '#'.
-/// It skips leading spaces, then reads the '#' followed by at least one digit.
+/// It skips leading spaces, then reads the '#' followed by an optional '-' and at least one digit.
+/// A `0x` or `0b` prefix after the '#' reads the digits as hexadecimal or binary instead, e.g. `#0xFF`
+/// or `#0b1010`. Those take up to 32 bits, so `#0xFFFFFFFF` is the same as `#-1`.
 ///
 /// # Arguments
 /// * `input` - A string potentially containing an integer operand.
@@ -23,22 +26,17 @@ use super::{label_parsers::label_usage, register_parser::register};
 /// # Returns
 /// * `IResult<&str, Token>` - A result containing either a `Token` representing the integer operand
 ///   or an error, along with any remaining unparsed input string.
-fn integer_operand(input: &str) -> IResult<&str, Token> {
+pub fn integer_operand(input: &str) -> IResult<&str, Token> {
     context(
         "integer_operand",
         // Skip any leading spaces
         preceded(
             multispace0, // skip spaces first
             terminated(
-                // Skip the '#' and read at least one digit
-                map_res(
-                    preceded(tag("#"), digit1), // skip the # first
-                    |reg_num: &str| {
-                        // Convert the string representation of the number to an i32 and create a Token::IntegerOperand
-                        Ok::<Token, &str>(Token::IntegerOperand {
-                            value: reg_num.parse::<i32>().unwrap(),
-                        })
-                    },
+                // Skip the '#' and read the number; digits running on into letters are an error, not a new token
+                terminated(
+                    preceded(tag("#"), alt((hex_integer, bin_integer, dec_integer))), // skip the # first
+                    not(satisfy(|c: char| c.is_alphanumeric())),
                 ),
                 alt((multispace0, line_ending, eof)),
             ),
@@ -46,6 +44,39 @@ fn integer_operand(input: &str) -> IResult<&str, Token> {
     )(input)
 }
 
+/// Reads an optional sign and at least one decimal digit
+fn dec_integer(input: &str) -> IResult<&str, Token> {
+    map_res(recognize(tuple((opt(char('-')), digit1))), |num: &str| {
+        // Convert the string representation of the number to an i32 and create a Token::IntegerOperand
+        num.parse::<i32>()
+            .map(|value| Token::IntegerOperand { value })
+    })(input)
+}
+
+/// Reads `0x` followed by hexadecimal digits
+fn hex_integer(input: &str) -> IResult<&str, Token> {
+    map_res(preceded(tag_no_case("0x"), hex_digit1), |num: &str| {
+        u32::from_str_radix(num, 16).map(|value| Token::IntegerOperand {
+            value: value as i32,
+        })
+    })(input)
+}
+
+/// Reads `0b` followed by binary digits
+fn bin_integer(input: &str) -> IResult<&str, Token> {
+    map_res(
+        preceded(
+            tag_no_case("0b"),
+            take_while1(|c: char| c == '0' || c == '1'),
+        ),
+        |num: &str| {
+            u32::from_str_radix(num, 2).map(|value| Token::IntegerOperand {
+                value: value as i32,
+            })
+        },
+    )(input)
+}
+
 fn float_operand(input: &str) -> IResult<&str, Token> {
     context(
         "float_operand",
@@ -86,13 +117,49 @@ fn float_operand(input: &str) -> IResult<&str, Token> {
     )(input)
 }
 
+/// Reads the raw text of a string up to its closing `quote`, stepping over backslash escapes so
+/// `'it\'s'` doesn't end early. The escapes themselves are decoded later by `unescape`.
+fn quoted_content(quote: char) -> impl FnMut(&str) -> IResult<&str, &str> {
+    move |input| {
+        recognize(many0_count(alt((
+            recognize(pair(char('\\'), anychar)),
+            recognize(satisfy(|c| c != quote && c != '\\')),
+        ))))(input)
+    }
+}
+
+/// Decodes the escape sequences in a string constant: `\n`, `\t`, `\\`, `\"`, `\'` and `\0`.
+/// Anything else after a backslash is returned as the error.
+pub fn unescape(raw: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('\'') => bytes.push(b'\''),
+            Some('0') => bytes.push(0),
+            Some(other) => return Err(format!("\\{}", other)),
+            None => return Err("\\".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
 fn ir_string_single_quota(input: &str) -> IResult<&str, Token> {
     context(
         "ir_string_single_quota",
         preceded(
             multispace0,
             terminated(
-                delimited(tag("'"), take_while(|c: char| c != '\''), tag("'")),
+                delimited(tag("'"), quoted_content('\''), tag("'")),
                 alt((multispace0, line_ending, eof)),
             ),
         ),
@@ -113,7 +180,7 @@ fn ir_string_double_quota(input: &str) -> IResult<&str, Token> {
         preceded(
             multispace0,
             terminated(
-                delimited(tag("\""), take_while(|c: char| c != '\"'), tag("\"")),
+                delimited(tag("\""), quoted_content('"'), tag("\"")),
                 alt((multispace0, line_ending, eof)),
             ),
         ),
@@ -155,6 +222,7 @@ mod tests {
 
     use super::{
         float_operand, integer_operand, ir_string, ir_string_double_quota, ir_string_single_quota,
+        unescape,
     };
 
     #[test]
@@ -171,6 +239,29 @@ mod tests {
 
         let result = integer_operand("# 10");
         assert_eq!(result.is_ok(), false);
+
+        let result = integer_operand("#-4");
+        assert_eq!(result, Ok(("", Token::IntegerOperand { value: -4 })));
+    }
+
+    #[test]
+    fn test_parse_radix_integer() {
+        assert_eq!(
+            integer_operand("#0xFF"),
+            Ok(("", Token::IntegerOperand { value: 255 }))
+        );
+        assert_eq!(
+            integer_operand("#0b1111"),
+            Ok(("", Token::IntegerOperand { value: 15 }))
+        );
+        assert_eq!(integer_operand("#0x1f"), integer_operand("#31"));
+        assert_eq!(integer_operand("#0b1010 "), integer_operand("#10 "));
+        assert_eq!(integer_operand("#0xFFFFFFFF"), integer_operand("#-1"));
+
+        assert_eq!(integer_operand("#0x").is_ok(), false);
+        assert_eq!(integer_operand("#0xFG").is_ok(), false);
+        assert_eq!(integer_operand("#0b102").is_ok(), false);
+        assert_eq!(integer_operand("#0x100000000").is_ok(), false);
     }
 
     #[test]
@@ -322,6 +413,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_escaped_strings() {
+        assert_eq!(
+            ir_string(r"'it\'s' "),
+            Ok((
+                "",
+                Token::IrString {
+                    name: r"it\'s".to_string()
+                }
+            ))
+        );
+        assert_eq!(unescape(r#"a\tb\n\\\"\'\0"#), Ok(b"a\tb\n\\\"'\0".to_vec()));
+        assert_eq!(unescape(r"bad\q"), Err(r"\q".to_string()));
+        assert_eq!(unescape("trailing\\"), Err("\\".to_string()));
+    }
+
     #[test]
     fn test_parse_float_operand() {
         let _ = vec!["#100.3", "#-100.3", "#1.0", "#0.0"].iter().map(|i| {
@@ -1,9 +1,20 @@
 use crate::assembler::instruction_parsers::AssemblerInstruction;
-use nom::{branch::alt, combinator::map, error::context, multi::many1, IResult};
+use nom::{
+    branch::alt,
+    combinator::map,
+    error::context,
+    multi::many1,
+    sequence::{preceded, terminated},
+    IResult,
+};
 
 use crate::assembler::SymbolTable;
 
-use super::{directive_parsers::directive, instruction_parsers::instruction};
+use super::{
+    comments::{skip_comments, DEFAULT_COMMENT_MARKER},
+    directive_parsers::directive,
+    instruction_parsers::instruction,
+};
 
 #[derive(Debug, PartialEq)]
 pub struct Program {
@@ -12,9 +23,10 @@ pub struct Program {
 
 impl Program {
     pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
-        let mut program_bytes = vec![];
+        // Almost every instruction is 4 bytes, only LOAD32 needs more room
+        let mut program_bytes = Vec::with_capacity(self.instructions.len() * 4);
         for instruction in &self.instructions {
-            program_bytes.append(&mut instruction.to_bytes(symbols));
+            instruction.to_bytes_into(&mut program_bytes, symbols);
         }
         program_bytes
     }
@@ -31,16 +43,31 @@ impl Program {
 /// 返回：
 /// - IResult<&str, Program> - 解析结果，包含解析得到的 Program 结构和剩余未解析的输入字符串。
 pub fn program(input: &str) -> IResult<&str, Program> {
+    program_with_comments(input, DEFAULT_COMMENT_MARKER)
+}
+
+/// Same as `program`, but comments start with `marker` instead of `;`
+pub fn program_with_comments<'a>(input: &'a str, marker: &str) -> IResult<&'a str, Program> {
     // 使用 context 组合器为解析过程提供上下文信息，当解析失败时能够提供更丰富的错误信息。
     // 这里将上下文命名为"program"，以便在错误消息中标识出是在解析程序级别的结构。
     context(
         "program",
         // 使用 map 组合器将解析结果转换为 Program 结构。
         // many1 组合器用于解析一个或多个指令或指令集，alt 组合器用于在指令和指令集之间进行选择。
-        map(many1(alt((instruction, directive))), |instructions| {
-            // 将解析到的指令封装到 Program 结构中。
-            Program { instructions }
-        }),
+        // 指令前后以 `marker` 开头的注释会被跳过
+        map(
+            terminated(
+                many1(preceded(
+                    skip_comments(marker),
+                    alt((instruction, directive)),
+                )),
+                skip_comments(marker),
+            ),
+            |instructions| {
+                // 将解析到的指令封装到 Program 结构中。
+                Program { instructions }
+            },
+        ),
     )(input)
 }
 
@@ -48,7 +75,7 @@ pub fn program(input: &str) -> IResult<&str, Program> {
 mod tests {
     use crate::assembler::SymbolTable;
 
-    use super::program;
+    use super::{program, program_with_comments};
 
     #[test]
     fn test_parse_program() {
@@ -65,6 +92,47 @@ mod tests {
         assert_eq!(1, p.instructions.len());
     }
 
+    #[test]
+    fn test_parse_program_comments() {
+        let symbols = SymbolTable::new();
+
+        // Full-line comments, including ones that read like instructions, produce nothing
+        let (leftover, p) = program(
+            "; counter setup\n; load $1 #99\nload $0 #10 ; init counter\nhlt ; done\n; end",
+        )
+        .unwrap();
+        assert_eq!(leftover, "");
+        assert_eq!(p.instructions.len(), 2);
+        let (_, plain) = program("load $0 #10\nhlt\n").unwrap();
+        assert_eq!(p.to_bytes(&symbols), plain.to_bytes(&symbols));
+
+        // A `;` inside a string literal is part of the string
+        let (leftover, p) = program("msg: .asciiz 'a;b' ; note\n").unwrap();
+        assert_eq!(leftover, "");
+        assert_eq!(
+            p.instructions[0].get_string_constant(),
+            Some("a;b".to_string())
+        );
+        // Even after an escaped quote
+        let (leftover, p) = program(r"msg: .asciiz 'a\';b' ; note").unwrap();
+        assert_eq!(leftover, "");
+        assert_eq!(
+            p.instructions[0].get_string_constant(),
+            Some(r"a\';b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_program_comment_marker() {
+        let (leftover, p) = program_with_comments("// setup\nhlt // stop\n", "//").unwrap();
+        assert_eq!(leftover, "");
+        assert_eq!(p.instructions.len(), 1);
+
+        // With another marker `;` is no longer a comment
+        let (leftover, _) = program_with_comments("hlt ; stop\n", "//").unwrap();
+        assert_eq!(leftover, "; stop\n");
+    }
+
     #[test]
     fn test_program_to_bytes() {
         let symbols = SymbolTable::new();
@@ -84,6 +152,30 @@ mod tests {
         // println!("load $0 #1000 ==To_Bytes==> {:?}", bytecode);
     }
 
+    #[test]
+    fn test_to_bytes_into_matches_to_bytes() {
+        let symbols = SymbolTable::new();
+        let (_, p) =
+            program("load $0 #100\nload32 $1 #305419896\nadd $0 $1 $2\nnot $0 $1\nhlt\n").unwrap();
+
+        // What the program used to be: every instruction's own bytes, one after another
+        let separate: Vec<u8> = p
+            .instructions
+            .iter()
+            .flat_map(|i| i.to_bytes(&symbols))
+            .collect();
+        assert_eq!(p.to_bytes(&symbols), separate);
+        assert_eq!(separate.len(), 24);
+
+        // Padding counts from where the instruction starts, not from the start of the buffer
+        let mut shared = vec![0xff; 3];
+        for instruction in &p.instructions {
+            instruction.to_bytes_into(&mut shared, &symbols);
+        }
+        assert_eq!(shared[..3], [0xff; 3]);
+        assert_eq!(shared[3..], separate[..]);
+    }
+
     #[test]
     fn test_complete_program() {
         let test_program = "  .data\nhello: .asciiz 'Hello everyone!'\n.code\nhlt";
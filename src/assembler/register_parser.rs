@@ -3,16 +3,20 @@ use crate::assembler::Token;
 use nom::{
     bytes::complete::tag,
     character::complete::{digit1, multispace0},
-    combinator::map_res,
+    combinator::map_opt,
     error::context,
     sequence::preceded,
     IResult,
 };
 
+/// The VM has 32 integer registers, `$0` through `$31`
+const REGISTER_COUNT: u8 = 32;
+
 /// Parses a register token from the input string.
 ///
 /// The register token starts with a '$' followed by at least one digit.
 /// This function skips leading spaces and expects the token to be in this specific format.
+/// Register numbers outside `0..32` are rejected here rather than failing in the VM later.
 ///
 /// # Arguments
 /// * `input` - The input string to parse.
@@ -27,13 +31,15 @@ pub fn register(input: &str) -> IResult<&str, Token> {
         preceded(
             multispace0, // skip spaces first
             // Skip the '$' and read at least one digit
-            map_res(
+            map_opt(
                 preceded(tag("$"), digit1), // skip the $ first
                 |reg_num: &str| {
                     // Convert the string representation of the register number to an unsigned 8-bit integer
-                    Ok::<Token, &str>(Token::Register {
-                        reg_num: reg_num.parse::<u8>().unwrap(),
-                    })
+                    reg_num
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|reg_num| *reg_num < REGISTER_COUNT)
+                        .map(|reg_num| Token::Register { reg_num })
                 },
             ),
         ),
@@ -43,6 +49,7 @@ pub fn register(input: &str) -> IResult<&str, Token> {
 #[cfg(test)]
 mod tests {
     use super::register;
+    use crate::assembler::Token;
 
     #[test]
     fn test_parse_register() {
@@ -55,4 +62,11 @@ mod tests {
         let result = register("$ 100");
         assert_eq!(result.is_ok(), false);
     }
+
+    #[test]
+    fn test_parse_register_range() {
+        assert_eq!(register("$31"), Ok(("", Token::Register { reg_num: 31 })));
+        assert_eq!(register("$32").is_ok(), false);
+        assert_eq!(register("$9999").is_ok(), false);
+    }
 }
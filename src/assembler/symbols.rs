@@ -3,6 +3,8 @@ pub struct Symbol {
     name: String,
     symbol_type: SymbolType,
     offset: Option<u32>,
+    /// The value of a constant declared with `.integer`
+    value: Option<i32>,
 }
 
 impl Symbol {
@@ -11,6 +13,7 @@ impl Symbol {
             name,
             symbol_type,
             offset: None,
+            value: None,
         }
     }
 
@@ -19,6 +22,7 @@ impl Symbol {
             name,
             symbol_type,
             offset: Some(offset),
+            value: None,
         }
     }
 }
@@ -67,6 +71,26 @@ impl SymbolTable {
         // false
     }
 
+    /// Turns the symbol into an integer constant with the given value
+    pub fn set_symbol_constant(&mut self, s: &str, value: i32) -> bool {
+        match self.symbols.iter_mut().find(|symbol| symbol.name == s) {
+            Some(symbol) => {
+                symbol.symbol_type = SymbolType::Integer;
+                symbol.value = Some(value);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The value of the integer constant with this name, if there is one
+    pub fn symbol_constant(&self, s: &str) -> Option<i32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == s)
+            .and_then(|symbol| symbol.value)
+    }
+
     pub fn symbol_value(&self, s: &str) -> Option<u32> {
         for symbol in &self.symbols {
             if symbol.name == s {
@@ -1,8 +1,7 @@
-use std::{fs::File, io::Read, path::Path, thread};
+use std::thread;
 
 use clap::Parser;
 use lrvm::{
-    assembler,
     cli::{self, CLI},
     repl,
     util::display::{self},
@@ -12,7 +11,6 @@ use lrvm::{
 extern crate nom;
 extern crate num_cpus;
 
-static NODE_ID_FILENAME: &'static str = ".node_id";
 static DEFAULT_NODE_LISTEN_HOST: &'static str = "127.0.0.1";
 static DEFAULT_NODE_LISTEN_PORT: &'static str = "65211";
 static DEFAULT_REMOTE_ACCESS_PORT: &'static str = "65201";
@@ -31,9 +29,19 @@ fn main() {
     };
 
     if cli.enable_remote_access {
+        let token = match cli::remote_token(
+            cli.remote_token.as_deref(),
+            cli.remote_token_file.as_deref(),
+        ) {
+            Ok(token) => token,
+            Err(e) => {
+                display::e_writeout(&format!("error: {}", e));
+                std::process::exit(1);
+            },
+        };
         let host = cli.listen_host.unwrap_or(DEFAULT_NODE_LISTEN_HOST.into());
         let port = cli.listen_port.unwrap_or(DEFAULT_REMOTE_ACCESS_PORT.into());
-        start_remote_server(host, port);
+        start_remote_server(host, port, token);
     }
 
     // Find or generate a unique node ID
@@ -56,50 +64,72 @@ fn main() {
     if let Some(command) = &cli.command {
         match command {
             cli::Vers::Run => {
-                display::writeout("RUNNING...");
+                if cli.file.is_none() {
+                    display::e_writeout(
+                        "lrvm run needs a file to run, e.g. lrvm run -f program.iasm",
+                    );
+                    std::process::exit(1);
+                }
             },
             cli::Vers::Print(v) => {
                 if let Some(text) = &v.content {
                     display::writeout(&format!("The user text: {:?}", text));
                 }
             },
+            cli::Vers::Assemble(args) => match cli::assemble_file(&args.input, &args.output) {
+                Ok(program) => {
+                    display::writeout(&format!("Wrote {} bytes to {}", program.len(), args.output));
+                    std::process::exit(0);
+                },
+                Err(errors) => {
+                    for error in &errors {
+                        display::e_writeout(&format!("error: {}", error));
+                    }
+                    std::process::exit(1);
+                },
+            },
         }
     }
 
     if let Some(filename) = &cli.file {
-        let program = read_file(&filename);
-        let mut asm = assembler::Assembler::new();
         let mut vm = vm::VM::new()
             .with_alias(alias)
             .with_cluster_bind(server_host, server_port);
         vm.logical_cores = num_threads;
-        if let Ok(p) = asm.assemble(&program) {
-            vm.add_bytes(p);
-            let events = vm.run();
-            display::writeout("虚拟机事件");
-            display::writeout("--------------------------");
-            for event in &events {
-                display::writeout(&format!("{:#?}", event));
-            }
+        cli::preload_registers(&mut vm, &cli.registers);
+        let events = match cli::run_file(&mut vm, filename) {
+            Ok(events) => events,
+            Err(errors) => {
+                for error in &errors {
+                    display::e_writeout(&format!("error: {}", error));
+                }
+                std::process::exit(1);
+            },
+        };
+        if cli.events_json {
+            println!("{}", vm::events_to_json(&events));
             std::process::exit(0);
         }
+        display::writeout("虚拟机事件");
+        display::writeout("--------------------------");
+        for event in &events {
+            display::writeout(&format!("{:#?}", event));
+        }
+        std::process::exit(0);
     } else {
         start_repl(alias, server_host, server_port);
     }
 }
 
-fn read_file(filename: &str) -> String {
-    let filename = Path::new(filename);
-    let mut fh = File::open(filename).expect("File not found");
-    let mut contents = String::new();
-    fh.read_to_string(&mut contents)
-        .expect("Error reading file");
-    contents
-}
-
-fn start_remote_server(listen_host: String, listen_port: String) {
+fn start_remote_server(listen_host: String, listen_port: String, token: Option<String>) {
     let _t = std::thread::spawn(move || {
         let mut sh = lrvm::remote::server::Server::new(listen_host, listen_port);
+        match token {
+            Some(token) => sh = sh.with_token(token),
+            None => display::writeout(
+                "Remote access has no token (--remote-token-file or LRVM_REMOTE_TOKEN), anyone who can connect gets a REPL",
+            ),
+        }
         sh.listen();
     });
 }
@@ -1,11 +1,20 @@
+use std::path::Path;
+
 use clap_derive::{Args, Parser, Subcommand};
 
+use crate::{
+    assembler::{assembler_errors::AssemblerError, Assembler, PIE_HEADER_PREFIX},
+    remote::client::MAX_TOKEN_LEN,
+    util::display,
+    vm::{VMEvent, VM},
+};
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 pub struct CLI {
-    /// Path to the .iasm or .ir file to run
-    #[arg(short('f'), long)]
+    /// Path to the .iasm or .ir file to run, or a .lrvm file that was already assembled
+    #[arg(short('f'), long, global = true)]
     pub file: Option<String>,
 
     /// Root directory where the lrvm VM should store its data. Defaults to /var/lib/lrvm.
@@ -32,6 +41,20 @@ pub struct CLI {
     #[arg(short('p'), long("bind-port"))]
     pub listen_port: Option<String>,
 
+    /// Secret that remote clients must send before they get a REPL. Without it anyone who can
+    /// reach the remote port gets one. Other local users can read it from `ps` or
+    /// /proc/<pid>/cmdline, prefer --remote-token-file or the LRVM_REMOTE_TOKEN variable.
+    #[arg(long("remote-token"), value_name("TOKEN"))]
+    pub remote_token: Option<String>,
+
+    /// Reads the remote access secret from the first line of this file instead of the command line
+    #[arg(
+        long("remote-token-file"),
+        value_name("PATH"),
+        conflicts_with("remote_token")
+    )]
+    pub remote_token_file: Option<String>,
+
     ///  Which address Iridium should listen for remote connections on from other Iridium VMs. Defaults to "127.0.0.1".
     #[arg(short('O'), long("server-bind-host"))]
     pub server_listen_host: Option<String>,
@@ -44,18 +67,155 @@ pub struct CLI {
     #[arg(long)]
     pub test: bool,
 
+    /// Sets a register before the program runs, e.g. --reg 0=5. Can be given more than once.
+    #[arg(long("reg"), value_name("INDEX=VALUE"), value_parser = parse_register_value)]
+    pub registers: Vec<(usize, i32)>,
+
+    /// Prints the events of the run as a JSON array instead of the readable listing
+    #[arg(long)]
+    pub events_json: bool,
+
     /// The command to run
     #[command(subcommand)]
     pub command: Option<Vers>,
 }
 
+/// Puts a program read from disk into the VM. Bytecode that already starts with the header, e.g.
+/// a saved .lrvm file, goes in as it is; anything else is assembled first.
+pub fn load_program(vm: &mut VM, contents: Vec<u8>) -> Result<(), Vec<AssemblerError>> {
+    if contents.starts_with(&PIE_HEADER_PREFIX) {
+        vm.add_bytes(contents);
+        return Ok(());
+    }
+
+    let source = String::from_utf8(contents).map_err(|e| {
+        vec![AssemblerError::ParseError {
+            error: format!("the file is neither bytecode nor UTF-8 source: {}", e),
+        }]
+    })?;
+    let mut asm = Assembler::new();
+    let program = asm.assemble(&source)?;
+    for warning in &asm.warnings {
+        display::e_writeout(&format!("warning: {}", warning));
+    }
+    vm.add_bytes(program);
+    vm.add_assertions(asm.assertions.clone());
+    Ok(())
+}
+
+/// Reads the program at `path` into the VM and runs it, as `lrvm run -f <path>` does
+pub fn run_file(vm: &mut VM, path: &str) -> Result<Vec<VMEvent>, Vec<AssemblerError>> {
+    let contents = std::fs::read(path).map_err(|e| {
+        vec![AssemblerError::FileReadError {
+            path: path.to_string(),
+            error: e.to_string(),
+        }]
+    })?;
+    load_program(vm, contents)?;
+    Ok(vm.run())
+}
+
+/// Assembles the source at `input` and writes the bytecode, header included, to `output`, as
+/// `lrvm assemble <input> -o <output>` does. Returns the bytecode that was written.
+pub fn assemble_file(input: &str, output: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+    let mut asm = Assembler::new();
+    let program = asm.assemble_files(&[Path::new(input)])?;
+    for warning in &asm.warnings {
+        display::e_writeout(&format!("warning: {}", warning));
+    }
+    std::fs::write(output, &program).map_err(|e| {
+        vec![AssemblerError::FileWriteError {
+            path: output.to_string(),
+            error: e.to_string(),
+        }]
+    })?;
+    Ok(program)
+}
+
+/// Environment variable read for the remote access secret when no flag gives one
+pub const REMOTE_TOKEN_ENV: &str = "LRVM_REMOTE_TOKEN";
+
+/// Finds the remote access secret: --remote-token, then --remote-token-file, then the
+/// LRVM_REMOTE_TOKEN environment variable. `Ok(None)` means none of them is set.
+pub fn remote_token(flag: Option<&str>, file: Option<&str>) -> Result<Option<String>, String> {
+    pick_remote_token(flag, file, std::env::var(REMOTE_TOKEN_ENV).ok())
+}
+
+fn pick_remote_token(
+    flag: Option<&str>,
+    file: Option<&str>,
+    env: Option<String>,
+) -> Result<Option<String>, String> {
+    let token = if let Some(token) = flag {
+        Some(token.to_string())
+    } else if let Some(path) = file {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("unable to read the remote token from {}: {}", path, e))?;
+        let token = contents.lines().next().unwrap_or("").trim();
+        if token.is_empty() {
+            return Err(format!("the remote token file {} is empty", path));
+        }
+        Some(token.to_string())
+    } else {
+        env.filter(|token| !token.is_empty())
+    };
+    // Clients can't send more than this, so a longer token would lock everyone out
+    match token {
+        Some(token) if token.len() > MAX_TOKEN_LEN => Err(format!(
+            "the remote token is {} bytes long, at most {} are allowed",
+            token.len(),
+            MAX_TOKEN_LEN
+        )),
+        token => Ok(token),
+    }
+}
+
+/// Copies the values given with --reg into the VM's registers
+pub fn preload_registers(vm: &mut VM, registers: &[(usize, i32)]) {
+    for (index, value) in registers {
+        vm.registers[*index] = *value;
+    }
+}
+
+/// Parses a --reg argument such as `3=-42` into a register index and value
+pub fn parse_register_value(arg: &str) -> Result<(usize, i32), String> {
+    let (index, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected INDEX=VALUE, got '{}'", arg))?;
+    let index: usize = index
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid register index '{}'", index))?;
+    if index >= 32 {
+        return Err(format!("register index {} is out of range (0..32)", index));
+    }
+    let value: i32 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid register value '{}', expected an i32", value))?;
+    Ok((index, value))
+}
+
 #[derive(Subcommand)]
 pub enum Vers {
-    /// Runs the file
+    /// Runs the file given with -f
     Run,
 
     /// Prints the text
     Print(InnertText),
+
+    /// Assembles a file into bytecode without running it
+    Assemble(AssembleArgs),
+}
+
+#[derive(Args)]
+pub struct AssembleArgs {
+    /// The .iasm file to assemble
+    pub input: String,
+
+    /// Where to write the bytecode, e.g. program.lrvm
+    #[arg(short('o'), long)]
+    pub output: String,
 }
 
 #[derive(Args)]
@@ -63,3 +223,137 @@ pub struct InnertText {
     /// The text to print
     pub content: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::{assembler::Assembler, remote::client::MAX_TOKEN_LEN, vm::VM};
+
+    use super::{
+        assemble_file, load_program, parse_register_value, pick_remote_token, preload_registers,
+        run_file, Vers, CLI,
+    };
+
+    #[test]
+    fn test_remote_token_sources() {
+        let path = std::env::temp_dir().join(format!("lrvm_token_{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let file = path.to_str();
+
+        let env = || Some("from-env".to_string());
+        assert_eq!(
+            pick_remote_token(Some("from-flag"), None, env()),
+            Ok(Some("from-flag".to_string()))
+        );
+        assert_eq!(
+            pick_remote_token(None, file, env()),
+            Ok(Some("from-file".to_string()))
+        );
+        assert_eq!(
+            pick_remote_token(None, None, env()),
+            Ok(Some("from-env".to_string()))
+        );
+        assert_eq!(pick_remote_token(None, None, Some(String::new())), Ok(None));
+        assert_eq!(pick_remote_token(None, None, None), Ok(None));
+        assert!(pick_remote_token(Some(&"x".repeat(MAX_TOKEN_LEN + 1)), None, None).is_err());
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(pick_remote_token(None, file, None).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(pick_remote_token(None, file, None).is_err());
+
+        // The flag and the file can't both be given
+        assert!(
+            CLI::try_parse_from(["lrvm", "--remote-token", "a", "--remote-token-file", "b"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_preload_registers() {
+        let cli = CLI::try_parse_from(["lrvm", "--reg", "0=5", "--reg", "1=10"]).unwrap();
+        let mut vm = VM::new();
+        preload_registers(&mut vm, &cli.registers);
+        assert_eq!(vm.registers[0], 5);
+        assert_eq!(vm.registers[1], 10);
+        assert!(vm.registers[2..].iter().all(|r| *r == 0));
+    }
+
+    #[test]
+    fn test_load_bytecode() {
+        let source = ".data\n.code\nload $0 #6\nload $1 #7\nmul $0 $1 $2\nhlt\n";
+        let path = std::env::temp_dir().join(format!("lrvm_cli_{}.lrvm", std::process::id()));
+        std::fs::write(&path, Assembler::new().assemble(source).unwrap()).unwrap();
+        let bytecode = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut assembled = VM::new();
+        load_program(&mut assembled, source.as_bytes().to_vec()).unwrap();
+        let mut loaded = VM::new();
+        load_program(&mut loaded, bytecode).unwrap();
+        assert_eq!(loaded.program, assembled.program);
+
+        assert!(assembled.run_summary().is_success());
+        assert!(loaded.run_summary().is_success());
+        assert_eq!(loaded.registers[2], 42);
+        assert_eq!(loaded.registers, assembled.registers);
+
+        assert!(load_program(&mut VM::new(), b"lod $0 #1".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_run_subcommand() {
+        let path = std::env::temp_dir().join(format!("lrvm_run_{}.iasm", std::process::id()));
+        std::fs::write(&path, ".data\n.code\nload $0 #9\nhlt\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let cli = CLI::try_parse_from(["lrvm", "run", "-f", &path]).unwrap();
+        assert!(matches!(cli.command, Some(Vers::Run)));
+        let mut vm = VM::new();
+        let result = run_file(&mut vm, cli.file.as_deref().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(vm.registers[0], 9);
+
+        assert!(run_file(&mut VM::new(), &path).is_err());
+    }
+
+    #[test]
+    fn test_assemble_subcommand() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("lrvm_assemble_{}.iasm", std::process::id()));
+        let output = dir.join(format!("lrvm_assemble_{}.lrvm", std::process::id()));
+        let source = ".data\nhi: .asciiz 'hi'\n.code\nload $0 #9\nhlt\n";
+        std::fs::write(&input, source).unwrap();
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+
+        let cli = CLI::try_parse_from(["lrvm", "assemble", input, "-o", output]).unwrap();
+        let args = match cli.command {
+            Some(Vers::Assemble(args)) => args,
+            _ => panic!("expected the assemble subcommand"),
+        };
+        let program = assemble_file(&args.input, &args.output).unwrap();
+        let written = std::fs::read(output).unwrap();
+        std::fs::remove_file(output).unwrap();
+        assert_eq!(written, program);
+        assert_eq!(
+            written,
+            Assembler::new().assemble(&format!("{}\n", source)).unwrap()
+        );
+
+        std::fs::write(input, ".data\n.code\nlod $0 #9\n").unwrap();
+        assert!(assemble_file(input, output).is_err());
+        std::fs::remove_file(input).unwrap();
+        assert!(std::fs::metadata(output).is_err());
+    }
+
+    #[test]
+    fn test_parse_register_value() {
+        assert_eq!(parse_register_value("31=-7"), Ok((31, -7)));
+        assert!(parse_register_value("32=1").is_err());
+        assert!(parse_register_value("1=99999999999").is_err());
+        assert!(parse_register_value("1").is_err());
+        assert!(CLI::try_parse_from(["lrvm", "--reg", "x=1"]).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
-    io::{BufRead, BufReader, BufWriter, Write},
-    net::TcpStream,
+    io::{self, BufReader, BufWriter, Write},
+    net::{SocketAddr, TcpStream},
     sync::{
         mpsc::{self, Receiver, Sender},
         Arc, Mutex,
@@ -10,7 +10,7 @@ use std::{
 
 use crate::util::display;
 
-use super::NodeAlias;
+use super::{message::LrvmMessage, NodeAlias};
 
 #[derive(Debug)]
 pub struct ClusterClient {
@@ -26,6 +26,8 @@ pub struct ClusterClient {
     // 如果有东西想要发送东西给这个客户端，它们可以克隆 `tx` 通道。
     _tx: Option<Arc<Mutex<Sender<String>>>>,
     raw_stream: TcpStream,
+    /// The other nodes of the cluster (alias, IP, port), as told by the HelloAck
+    peers: Vec<(NodeAlias, String, String)>,
 }
 
 impl ClusterClient {
@@ -40,36 +42,62 @@ impl ClusterClient {
             rx: Some(Arc::new(Mutex::new(rx))),
             _tx: Some(Arc::new(Mutex::new(tx))),
             raw_stream,
+            peers: vec![],
         }
     }
 
     pub fn run(&mut self) {
         // 在后台线程中启动 recv_loop
+        // 传入的消息由持有该连接的一方（例如集群服务器）读取和处理
         self.recv_loop();
-        let mut buf = String::new();
-        // 循环处理传入数据，等待数据。
-        loop {
-            match self.reader.read_line(&mut buf) {
-                Ok(_) => {
-                    buf.trim_end();
-                },
-                Err(e) => {
-                    display::e_writeout(&format!("Error receiving: {:#?}", e));
-                },
-            }
-        }
     }
 
     pub fn send_hello(&mut self) {
-        let alias = self.alias.clone();
-        let alias = alias.unwrap();
-        if self.raw_stream.write(&alias.as_bytes()).is_ok() {
+        let hello = LrvmMessage::Hello {
+            alias: self.alias.clone().unwrap(),
+        };
+        if hello.write_to(&mut self.raw_stream).is_ok() {
             display::writeout("Hello sent!");
         } else {
             display::e_writeout("Error sending hello!");
         }
     }
 
+    /// Waits for the HelloAck that answers our Hello and records the peers it lists.
+    /// Returns the alias of the node we connected to.
+    pub fn read_hello_ack(&mut self) -> io::Result<NodeAlias> {
+        match LrvmMessage::read_from(&mut self.reader)? {
+            LrvmMessage::HelloAck { alias, nodes } => {
+                self.peers = nodes;
+                Ok(alias)
+            },
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected a HelloAck, got {:?}", other),
+            )),
+        }
+    }
+
+    pub fn peers(&self) -> &[(NodeAlias, String, String)] {
+        &self.peers
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.raw_stream.peer_addr().ok()
+    }
+
+    /// Tells the node on the other end that we are leaving the cluster
+    pub fn send_goodbye(&mut self) {
+        let goodbye = LrvmMessage::Goodbye {
+            alias: self.alias.clone().unwrap_or_default(),
+        };
+        if goodbye.write_to(&mut self.raw_stream).is_ok() {
+            display::writeout("Goodbye sent!");
+        } else {
+            display::e_writeout("Error sending goodbye!");
+        }
+    }
+
     pub fn with_alias(mut self, alias: NodeAlias) -> Self {
         self.alias = Some(alias);
         self
@@ -98,9 +126,8 @@ impl ClusterClient {
                             },
                         }
                     },
-                    Err(e) => {
-                        display::e_writeout(&format!("Error writing to client: {}", e));
-                    },
+                    // All senders are gone, which means the client was dropped
+                    Err(_) => break,
                 }
             }
         });
@@ -43,6 +43,30 @@ impl Manager {
         self.clients.remove(&alias).is_some()
     }
 
+    /// Tells every connected node that we are leaving the cluster, then forgets about them
+    pub fn disconnect_all(&mut self) {
+        for (_, client) in self.clients.drain() {
+            if let Ok(mut client) = client.write() {
+                client.send_goodbye();
+            }
+        }
+    }
+
+    /// Alias, IP and port of every connected node, as sent in a HelloAck
+    pub fn get_client_addrs(&self) -> Vec<(NodeAlias, String, String)> {
+        self.clients
+            .iter()
+            .map(|(alias, client)| {
+                let addr = client.read().ok().and_then(|client| client.peer_addr());
+                let (ip, port) = match addr {
+                    Some(addr) => (addr.ip().to_string(), addr.port().to_string()),
+                    None => (String::new(), String::new()),
+                };
+                (alias.clone(), ip, port)
+            })
+            .collect()
+    }
+
     pub fn get_client_names(&self) -> Vec<String> {
         display::writeout("Getting client names...");
         let results: Vec<String> = self.clients.keys().map(|k| k.into()).collect();
@@ -59,5 +83,6 @@ mod test {
     #[test]
     fn test_create_manager() {
         let test_manager = Manager::new();
+        assert!(test_manager.get_client_names().is_empty());
     }
 }
@@ -1,3 +1,8 @@
+use std::io::{self, Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum LrvmMessage {
     Hello {
         alias: String,
@@ -8,4 +13,169 @@ pub enum LrvmMessage {
         /// The others nodes (alias, IP, port)
         nodes: Vec<(String, String, String)>,
     },
+    /// Sent by a node that is leaving the cluster, so peers can drop it
+    Goodbye {
+        alias: String,
+    },
+}
+
+impl LrvmMessage {
+    const HELLO: u8 = 0;
+    const HELLO_ACK: u8 = 1;
+    const GOODBYE: u8 = 2;
+    /// 超过这个长度的帧视为无效, 避免一个错误的长度字段申请过多内存
+    const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+    /// Encodes the message as a frame: a little endian u32 payload length followed by the payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        match self {
+            LrvmMessage::Hello { alias } => {
+                payload.push(LrvmMessage::HELLO);
+                write_string(&mut payload, alias);
+            },
+            LrvmMessage::HelloAck { alias, nodes } => {
+                payload.push(LrvmMessage::HELLO_ACK);
+                write_string(&mut payload, alias);
+                payload
+                    .write_u32::<LittleEndian>(nodes.len() as u32)
+                    .unwrap();
+                for (node_alias, ip, port) in nodes {
+                    write_string(&mut payload, node_alias);
+                    write_string(&mut payload, ip);
+                    write_string(&mut payload, port);
+                }
+            },
+            LrvmMessage::Goodbye { alias } => {
+                payload.push(LrvmMessage::GOODBYE);
+                write_string(&mut payload, alias);
+            },
+        }
+
+        let mut frame = vec![];
+        frame
+            .write_u32::<LittleEndian>(payload.len() as u32)
+            .unwrap();
+        frame.append(&mut payload);
+        frame
+    }
+
+    /// Decodes a single frame produced by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<LrvmMessage> {
+        LrvmMessage::read_from(&mut Cursor::new(bytes))
+    }
+
+    /// Writes the framed message to the given writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        writer.flush()
+    }
+
+    /// Blocks until one whole frame has been read from the given reader
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<LrvmMessage> {
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        if len > LrvmMessage::MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Frame of {} bytes is too large", len),
+            ));
+        }
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload)?;
+
+        let mut rdr = Cursor::new(payload);
+        match rdr.read_u8()? {
+            LrvmMessage::HELLO => Ok(LrvmMessage::Hello {
+                alias: read_string(&mut rdr)?,
+            }),
+            LrvmMessage::HELLO_ACK => {
+                let alias = read_string(&mut rdr)?;
+                let count = rdr.read_u32::<LittleEndian>()?;
+                let mut nodes = vec![];
+                for _ in 0..count {
+                    nodes.push((
+                        read_string(&mut rdr)?,
+                        read_string(&mut rdr)?,
+                        read_string(&mut rdr)?,
+                    ));
+                }
+                Ok(LrvmMessage::HelloAck { alias, nodes })
+            },
+            LrvmMessage::GOODBYE => Ok(LrvmMessage::Goodbye {
+                alias: read_string(&mut rdr)?,
+            }),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown message type: {}", other),
+            )),
+        }
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    buf.write_u32::<LittleEndian>(s.len() as u32).unwrap();
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LrvmMessage;
+
+    #[test]
+    fn test_hello_round_trip() {
+        let msg = LrvmMessage::Hello {
+            alias: "node1".to_string(),
+        };
+        let decoded = LrvmMessage::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(msg, decoded);
+    }
+
+    #[test]
+    fn test_hello_ack_round_trip() {
+        let msg = LrvmMessage::HelloAck {
+            alias: "node1".to_string(),
+            nodes: vec![
+                (
+                    "node2".to_string(),
+                    "127.0.0.1".to_string(),
+                    "2254".to_string(),
+                ),
+                (
+                    "node3".to_string(),
+                    "10.0.0.3".to_string(),
+                    "2255".to_string(),
+                ),
+                ("节点4".to_string(), "::1".to_string(), "2256".to_string()),
+            ],
+        };
+        let decoded = LrvmMessage::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(msg, decoded);
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        assert!(LrvmMessage::from_bytes(&[0xff, 0xff, 0xff, 0xff, 0]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_message_type() {
+        // length 1, type 9
+        assert!(LrvmMessage::from_bytes(&[1, 0, 0, 0, 9]).is_err());
+    }
+
+    #[test]
+    fn test_goodbye_round_trip() {
+        let msg = LrvmMessage::Goodbye {
+            alias: "node1".to_string(),
+        };
+        let decoded = LrvmMessage::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(msg, decoded);
+    }
 }
@@ -1,32 +1,373 @@
 use std::{
-    io::Read,
-    net::{SocketAddr, TcpListener},
-    sync::{Arc, RwLock},
+    io::{self, BufReader},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::AtomicBool, Arc, RwLock},
     thread,
 };
 
-use crate::util::display;
+use crate::util::{display, net};
 
-use super::{client::ClusterClient, manager::Manager};
+use super::{client::ClusterClient, manager::Manager, message::LrvmMessage};
 
-pub fn listen(addr: SocketAddr, connection_manager: Arc<RwLock<Manager>>) {
+/// Accepts nodes joining the cluster. `alias` is how this node introduces itself to them.
+/// Only fails if the address can't be bound, a bad connection is logged and skipped.
+pub fn listen(
+    addr: SocketAddr,
+    alias: String,
+    connection_manager: Arc<RwLock<Manager>>,
+) -> io::Result<()> {
+    listen_until(
+        addr,
+        alias,
+        connection_manager,
+        Arc::new(AtomicBool::new(false)),
+    )
+}
+
+/// Same as `listen`, but stops accepting nodes and returns once `shutdown` is set.
+/// Nodes that already joined stay connected.
+pub fn listen_until(
+    addr: SocketAddr,
+    alias: String,
+    connection_manager: Arc<RwLock<Manager>>,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<()> {
     display::writeout("Initializing Cluster server...");
-    let listener = TcpListener::bind(addr).unwrap();
+    let listener = TcpListener::bind(addr)?;
 
-    for stream in listener.incoming() {
+    for stream in net::incoming_until(&listener, &shutdown)? {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                display::e_writeout(&format!("Error accepting a node: {}", e));
+                continue;
+            },
+        };
         let cmgr = connection_manager.clone();
+        let server_alias = alias.clone();
         display::writeout("New Node connected!");
-        let stream = stream.unwrap();
         thread::spawn(move || {
-            let mut buf = [0; 1024];
-            let mut client = ClusterClient::new(stream);
-            // Once this call succeeds, we'll hopefully have the node alias in the string buffer
-            let bytes_read = client.reader.read(&mut buf).unwrap();
-            let alias = String::from_utf8_lossy(&buf[0..bytes_read]);
-            let mut cmgr_lock = cmgr.write().unwrap();
-            cmgr_lock.add_client(alias.into_owned(), client);
-            // let mut client = ClusterClient::new(stream);
-            // client.run();
+            handle_connection(stream, server_alias, cmgr);
+        });
+    }
+    Ok(())
+}
+
+/// Registers the node on the other end of `stream`, answers with a HelloAck listing the nodes
+/// already in the cluster, and then handles the messages it sends until it says goodbye or
+/// disconnects
+fn handle_connection(
+    stream: TcpStream,
+    server_alias: String,
+    connection_manager: Arc<RwLock<Manager>>,
+) {
+    // The inbound side of the connection stays with this thread, the client only writes to it
+    let mut reader = match stream.try_clone() {
+        Ok(inbound) => BufReader::new(inbound),
+        Err(e) => {
+            display::e_writeout(&format!("Error setting up the node connection: {}", e));
+            return;
+        },
+    };
+    // A node introduces itself with a Hello before anything else
+    let alias = match LrvmMessage::read_from(&mut reader) {
+        Ok(LrvmMessage::Hello { alias }) => alias,
+        Ok(other) => {
+            display::e_writeout(&format!(
+                "Expected a Hello from the new node, got {:?}",
+                other
+            ));
+            return;
+        },
+        Err(e) => {
+            display::e_writeout(&format!("Error reading Hello from the new node: {}", e));
+            return;
+        },
+    };
+    let client = ClusterClient::new(stream);
+    let nodes = {
+        let mut manager = match connection_manager.write() {
+            Ok(manager) => manager,
+            Err(_) => {
+                display::e_writeout("Connection manager is unusable, dropping the new node");
+                return;
+            },
+        };
+        let nodes = manager.get_client_addrs();
+        // Another node already goes by this alias, and its Goodbye must stay the only way to drop it
+        if !manager.add_client(alias.clone(), client) {
+            return;
+        }
+        nodes
+    };
+
+    let ack = LrvmMessage::HelloAck {
+        alias: server_alias,
+        nodes,
+    };
+    if let Err(e) = ack.write_to(reader.get_mut()) {
+        display::e_writeout(&format!("Error sending HelloAck: {}", e));
+    }
+
+    while let Ok(message) = LrvmMessage::read_from(&mut reader) {
+        if let LrvmMessage::Goodbye { alias: leaving } = message {
+            // Only the node registered on this connection is dropped, whatever alias it claims
+            if leaving != alias {
+                display::e_writeout(&format!(
+                    "Node {} said goodbye as {}, dropping {} only",
+                    alias, leaving, alias
+                ));
+            }
+            display::writeout(&format!("Node {} left the cluster", alias));
+            match connection_manager.write() {
+                Ok(mut manager) => {
+                    manager.del_client(alias);
+                },
+                Err(_) => display::e_writeout("Connection manager is unusable, can't drop node"),
+            }
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{Shutdown, TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc, RwLock,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::cluster::{client::ClusterClient, manager::Manager, message::LrvmMessage};
+
+    use super::{handle_connection, listen, listen_until};
+
+    /// Polls the manager until `check` holds, giving up after about a second
+    fn wait_for(manager: &Arc<RwLock<Manager>>, check: impl Fn(&Manager) -> bool) -> bool {
+        for _ in 0..100 {
+            if check(&manager.read().unwrap()) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_goodbye_removes_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        let cmgr = manager.clone();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, "server".to_string(), cmgr);
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        LrvmMessage::Hello {
+            alias: "node1".to_string(),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        assert!(wait_for(&manager, |m| m.get_client_names() == vec!["node1"]));
+
+        LrvmMessage::Goodbye {
+            alias: "node1".to_string(),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        assert!(wait_for(&manager, |m| m.get_client_names().is_empty()));
+    }
+
+    #[test]
+    fn test_goodbye_cannot_remove_other_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        let cmgr = manager.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let cmgr = cmgr.clone();
+                let stream = stream.unwrap();
+                thread::spawn(move || handle_connection(stream, "server".to_string(), cmgr));
+            }
+        });
+
+        let mut victim =
+            ClusterClient::new(TcpStream::connect(addr).unwrap()).with_alias("node1".to_string());
+        victim.send_hello();
+        assert_eq!(victim.read_hello_ack().unwrap(), "server");
+        let mut stream = TcpStream::connect(addr).unwrap();
+        LrvmMessage::Hello {
+            alias: "node2".to_string(),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        assert!(wait_for(&manager, |m| m.get_client_names().len() == 2));
+
+        // node2 leaves claiming to be node1, which drops node2 and keeps node1
+        LrvmMessage::Goodbye {
+            alias: "node1".to_string(),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        assert!(wait_for(&manager, |m| m.get_client_names() == vec!["node1"]));
+    }
+
+    #[test]
+    fn test_duplicate_alias_is_not_added() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        let cmgr = manager.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let cmgr = cmgr.clone();
+                let stream = stream.unwrap();
+                thread::spawn(move || handle_connection(stream, "server".to_string(), cmgr));
+            }
+        });
+
+        let mut first =
+            ClusterClient::new(TcpStream::connect(addr).unwrap()).with_alias("node1".to_string());
+        first.send_hello();
+        assert_eq!(first.read_hello_ack().unwrap(), "server");
+
+        // The impostor gets no HelloAck, so its Goodbye never reaches a handler
+        let mut impostor =
+            ClusterClient::new(TcpStream::connect(addr).unwrap()).with_alias("node1".to_string());
+        impostor.send_hello();
+        assert!(impostor.read_hello_ack().is_err());
+        impostor.send_goodbye();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(manager.read().unwrap().get_client_names(), vec!["node1"]);
+    }
+
+    #[test]
+    fn test_connection_without_hello_is_not_added() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        let cmgr = manager.clone();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, "server".to_string(), cmgr);
         });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"node1").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        server.join().unwrap();
+        assert!(manager.read().unwrap().get_client_names().is_empty());
+    }
+
+    #[test]
+    fn test_listen_survives_early_disconnect() {
+        // Find a free port, then give it to `listen`
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        let cmgr = manager.clone();
+        thread::spawn(move || listen(addr, "server".to_string(), cmgr));
+
+        let mut connected = None;
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(addr) {
+                connected = Some(stream);
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Goes away before saying hello
+        drop(connected.expect("server never started"));
+        // Sends junk
+        let mut junk = TcpStream::connect(addr).unwrap();
+        junk.write_all(&[0xff; 16]).unwrap();
+        drop(junk);
+
+        let mut node =
+            ClusterClient::new(TcpStream::connect(addr).unwrap()).with_alias("node1".to_string());
+        node.send_hello();
+        assert_eq!(node.read_hello_ack().unwrap(), "server");
+        assert!(wait_for(&manager, |m| m.get_client_names() == vec!["node1"]));
+    }
+
+    #[test]
+    fn test_listen_until_shutdown() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (done_tx, done_rx) = mpsc::channel();
+        let (cmgr, flag) = (manager.clone(), shutdown.clone());
+        thread::spawn(move || {
+            let result = listen_until(addr, "server".to_string(), cmgr, flag);
+            done_tx.send(result.is_ok()).unwrap();
+        });
+
+        let mut connected = None;
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(addr) {
+                connected = Some(stream);
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut node = ClusterClient::new(connected.expect("server never started"))
+            .with_alias("node1".to_string());
+        node.send_hello();
+        assert_eq!(node.read_hello_ack().unwrap(), "server");
+
+        shutdown.store(true, Ordering::SeqCst);
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(2)), Ok(true));
+    }
+
+    #[test]
+    fn test_listen_bind_failure() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        assert!(listen(addr, "server".to_string(), manager).is_err());
+    }
+
+    #[test]
+    fn test_hello_ack_lists_existing_nodes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = Arc::new(RwLock::new(Manager::new()));
+        let cmgr = manager.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let cmgr = cmgr.clone();
+                let stream = stream.unwrap();
+                thread::spawn(move || handle_connection(stream, "server".to_string(), cmgr));
+            }
+        });
+
+        let mut node1 =
+            ClusterClient::new(TcpStream::connect(addr).unwrap()).with_alias("node1".to_string());
+        node1.send_hello();
+        assert_eq!(node1.read_hello_ack().unwrap(), "server");
+        assert!(node1.peers().is_empty());
+
+        let mut node2 =
+            ClusterClient::new(TcpStream::connect(addr).unwrap()).with_alias("node2".to_string());
+        node2.send_hello();
+        assert_eq!(node2.read_hello_ack().unwrap(), "server");
+        assert_eq!(node2.peers().len(), 1);
+        let (alias, ip, port) = &node2.peers()[0];
+        assert_eq!(alias, "node1");
+        assert_eq!(ip, "127.0.0.1");
+        assert!(port.parse::<u16>().is_ok());
     }
 }
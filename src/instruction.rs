@@ -1,55 +1,83 @@
+use std::{error::Error, fmt};
+
 /// Represents an opcode, which tells our interpreter what to do with the following operands
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Opcode {
-    LOAD,    // 0
-    ADD,     // 1
-    SUB,     // 2
-    MUL,     // 3
-    DIV,     // 4
-    HLT,     // 5
-    JMP,     // 6
-    JMPF,    // 7
-    JMPB,    // 8
-    EQ,      // 9
-    NEQ,     // 10
-    GTE,     // 11
-    LTE,     // 12
-    LT,      // 13
-    GT,      // 14
-    JMPE,    // 15
-    NOP,     // 16
-    ALOC,    // 17
-    INC,     // 18
-    DEC,     // 19
-    DJMPE,   // 20
-    IGL,     // 100
-    PRTS,    // 21
-    LOADF64, // 22
-    ADDF64,  // 23
-    SUBF64,  // 24
-    MULF64,  // 25
-    DIVF64,  // 26
-    EQF64,   // 27
-    NEQF64,  // 28
-    GTF64,   // 29
-    GTEF64,  // 30
-    LTF64,   // 31
-    LTEF64,  // 32
-    SHL,     // 33
-    SHR,     // 34
-    AND,     // 35
-    OR,      // 36
-    XOR,     // 37
-    NOT,     // 38
-    LUI,     // 39
-    CLOOP,   // 40
-    LOOP,    // 41
-    LOADM,   // 42
-    SETM,    // 43
-    PUSH,    // 44
-    POP,     // 45
-    CALL,    // 46
-    RET,     // 47
+    LOAD,     // 0
+    ADD,      // 1
+    SUB,      // 2
+    MUL,      // 3
+    DIV,      // 4
+    HLT,      // 5
+    JMP,      // 6
+    JMPF,     // 7
+    JMPB,     // 8
+    EQ,       // 9
+    NEQ,      // 10
+    GTE,      // 11
+    LTE,      // 12
+    LT,       // 13
+    GT,       // 14
+    JMPE,     // 15
+    NOP,      // 16
+    ALOC,     // 17
+    INC,      // 18
+    DEC,      // 19
+    DJMPE,    // 20
+    IGL,      // 100
+    PRTS,     // 21
+    LOADF64,  // 22
+    ADDF64,   // 23
+    SUBF64,   // 24
+    MULF64,   // 25
+    DIVF64,   // 26
+    EQF64,    // 27
+    NEQF64,   // 28
+    GTF64,    // 29
+    GTEF64,   // 30
+    LTF64,    // 31
+    LTEF64,   // 32
+    SHL,      // 33
+    SHR,      // 34
+    AND,      // 35
+    OR,       // 36
+    XOR,      // 37
+    NOT,      // 38
+    LUI,      // 39
+    CLOOP,    // 40
+    LOOP,     // 41
+    LOADM,    // 42
+    SETM,     // 43
+    PUSH,     // 44
+    POP,      // 45
+    CALL,     // 46
+    RET,      // 47
+    SYSCALL,  // 48
+    FILL,     // 49
+    ROL,      // 50
+    ROR,      // 51
+    CLZ,      // 52
+    POPCNT,   // 53
+    LOADMO,   // 54
+    LOAD32,   // 55
+    PUSHA,    // 56
+    POPA,     // 57
+    RAND,     // 58
+    TIME,     // 59
+    READI,    // 60
+    CLAMP,    // 61
+    MIN,      // 62
+    MAX,      // 63
+    MINF64,   // 64
+    MAXF64,   // 65
+    CLAMPF64, // 66
+    ADDI,     // 67
+    TAS,      // 68
+    COPYRO,   // 69
+    HEAPSZ,   // 70
+    MOD,      // 71
+    MOV,      // 72
+    PRTI,     // 73
 }
 
 impl Into<u8> for Opcode {
@@ -103,6 +131,32 @@ impl Into<u8> for Opcode {
             Opcode::POP => 45,
             Opcode::CALL => 46,
             Opcode::RET => 47,
+            Opcode::SYSCALL => 48,
+            Opcode::FILL => 49,
+            Opcode::ROL => 50,
+            Opcode::ROR => 51,
+            Opcode::CLZ => 52,
+            Opcode::POPCNT => 53,
+            Opcode::LOADMO => 54,
+            Opcode::LOAD32 => 55,
+            Opcode::PUSHA => 56,
+            Opcode::POPA => 57,
+            Opcode::RAND => 58,
+            Opcode::TIME => 59,
+            Opcode::READI => 60,
+            Opcode::CLAMP => 61,
+            Opcode::MIN => 62,
+            Opcode::MAX => 63,
+            Opcode::MINF64 => 64,
+            Opcode::MAXF64 => 65,
+            Opcode::CLAMPF64 => 66,
+            Opcode::ADDI => 67,
+            Opcode::TAS => 68,
+            Opcode::COPYRO => 69,
+            Opcode::HEAPSZ => 70,
+            Opcode::MOD => 71,
+            Opcode::MOV => 72,
+            Opcode::PRTI => 73,
             Opcode::IGL => 100,
         }
     }
@@ -159,66 +213,293 @@ impl From<u8> for Opcode {
             45 => Opcode::POP,
             46 => Opcode::CALL,
             47 => Opcode::RET,
+            48 => Opcode::SYSCALL,
+            49 => Opcode::FILL,
+            50 => Opcode::ROL,
+            51 => Opcode::ROR,
+            52 => Opcode::CLZ,
+            53 => Opcode::POPCNT,
+            54 => Opcode::LOADMO,
+            55 => Opcode::LOAD32,
+            56 => Opcode::PUSHA,
+            57 => Opcode::POPA,
+            58 => Opcode::RAND,
+            59 => Opcode::TIME,
+            60 => Opcode::READI,
+            61 => Opcode::CLAMP,
+            62 => Opcode::MIN,
+            63 => Opcode::MAX,
+            64 => Opcode::MINF64,
+            65 => Opcode::MAXF64,
+            66 => Opcode::CLAMPF64,
+            67 => Opcode::ADDI,
+            68 => Opcode::TAS,
+            69 => Opcode::COPYRO,
+            70 => Opcode::HEAPSZ,
+            71 => Opcode::MOD,
+            72 => Opcode::MOV,
+            73 => Opcode::PRTI,
             _ => Opcode::IGL,
         }
     }
 }
 
-impl From<&str> for Opcode {
-    fn from(value: &str) -> Self {
+/// The string given to `Opcode::try_from` isn't the mnemonic of any instruction
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnknownMnemonic {
+    pub mnemonic: String,
+}
+
+impl fmt::Display for UnknownMnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown opcode: {}", self.mnemonic)
+    }
+}
+
+impl Error for UnknownMnemonic {}
+
+impl TryFrom<&str> for Opcode {
+    type Error = UnknownMnemonic;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Opcode::try_from_str(value).ok_or_else(|| UnknownMnemonic {
+            mnemonic: value.to_string(),
+        })
+    }
+}
+
+impl Opcode {
+    /// What `From<&str>` used to do: anything that isn't a mnemonic becomes `IGL`.
+    /// Prefer `Opcode::try_from`, which tells a typo apart from an explicit `igl`.
+    ///
+    /// Breaking change: `impl From<&str> for Opcode` is gone, so `Opcode::from(s)` and
+    /// `s.into()` no longer compile. Through the standard library's blanket
+    /// `TryFrom<U> for T where U: Into<T>` it would clash with `TryFrom<&str>`.
+    /// Call `Opcode::from_str_lossy(s)` instead to keep the old behaviour:
+    ///
+    /// ```
+    /// use lrvm::instruction::Opcode;
+    ///
+    /// // was: let opcode: Opcode = "hlt".into();
+    /// assert_eq!(Opcode::from_str_lossy("hlt"), Opcode::HLT);
+    /// assert_eq!(Opcode::from_str_lossy("typo"), Opcode::IGL);
+    /// ```
+    pub fn from_str_lossy(value: &str) -> Opcode {
+        Opcode::try_from_str(value).unwrap_or(Opcode::IGL)
+    }
+
+    /// Looks up an instruction by its mnemonic, returning `None` for anything that isn't one
+    pub fn try_from_str(value: &str) -> Option<Opcode> {
         match value {
-            "load" => Opcode::LOAD,
-            "add" => Opcode::ADD,
-            "sub" => Opcode::SUB,
-            "mul" => Opcode::MUL,
-            "div" => Opcode::DIV,
-            "hlt" => Opcode::HLT,
-            "jmp" => Opcode::JMP,
-            "jmpf" => Opcode::JMPF,
-            "jmpb" => Opcode::JMPB,
-            "eq" => Opcode::EQ,
-            "neq" => Opcode::NEQ,
-            "gte" => Opcode::GTE,
-            "lte" => Opcode::LTE,
-            "lt" => Opcode::LT,
-            "gt" => Opcode::GT,
-            "jmpe" => Opcode::JMPE,
-            "nop" => Opcode::NOP,
-            "aloc" => Opcode::ALOC,
-            "inc" => Opcode::INC,
-            "dec" => Opcode::DEC,
-            "djmpe" => Opcode::DJMPE,
-            "igl" => Opcode::IGL,
-            "prts" => Opcode::PRTS,
-            "loadf64" => Opcode::LOADF64,
-            "addf64" => Opcode::ADDF64,
-            "subf64" => Opcode::SUBF64,
-            "mulf64" => Opcode::MULF64,
-            "divf64" => Opcode::DIVF64,
-            "eqf64" => Opcode::EQF64,
-            "neqf64" => Opcode::NEQF64,
-            "gtf64" => Opcode::GTF64,
-            "gtef64" => Opcode::GTEF64,
-            "ltf64" => Opcode::LTF64,
-            "ltef64" => Opcode::LTEF64,
-            "shl" => Opcode::SHL,
-            "shr" => Opcode::SHR,
-            "and" => Opcode::AND,
-            "or" => Opcode::OR,
-            "xor" => Opcode::XOR,
-            "not" => Opcode::NOT,
-            "lui" => Opcode::LUI,
-            "cloop" => Opcode::CLOOP,
-            "loop" => Opcode::LOOP,
-            "loadm" => Opcode::LOADM,
-            "setm" => Opcode::SETM,
-            "push" => Opcode::PUSH,
-            "pop" => Opcode::POP,
-            "call" => Opcode::CALL,
-            "ret" => Opcode::RET,
-            _ => Opcode::IGL,
+            "load" => Some(Opcode::LOAD),
+            "add" => Some(Opcode::ADD),
+            "sub" => Some(Opcode::SUB),
+            "mul" => Some(Opcode::MUL),
+            "div" => Some(Opcode::DIV),
+            "hlt" => Some(Opcode::HLT),
+            "jmp" => Some(Opcode::JMP),
+            "jmpf" => Some(Opcode::JMPF),
+            "jmpb" => Some(Opcode::JMPB),
+            "eq" => Some(Opcode::EQ),
+            "neq" => Some(Opcode::NEQ),
+            "gte" => Some(Opcode::GTE),
+            "lte" => Some(Opcode::LTE),
+            "lt" => Some(Opcode::LT),
+            "gt" => Some(Opcode::GT),
+            "jmpe" => Some(Opcode::JMPE),
+            "nop" => Some(Opcode::NOP),
+            "aloc" => Some(Opcode::ALOC),
+            "inc" => Some(Opcode::INC),
+            "dec" => Some(Opcode::DEC),
+            "djmpe" => Some(Opcode::DJMPE),
+            "igl" => Some(Opcode::IGL),
+            "prts" => Some(Opcode::PRTS),
+            "loadf64" => Some(Opcode::LOADF64),
+            "addf64" => Some(Opcode::ADDF64),
+            "subf64" => Some(Opcode::SUBF64),
+            "mulf64" => Some(Opcode::MULF64),
+            "divf64" => Some(Opcode::DIVF64),
+            "eqf64" => Some(Opcode::EQF64),
+            "neqf64" => Some(Opcode::NEQF64),
+            "gtf64" => Some(Opcode::GTF64),
+            "gtef64" => Some(Opcode::GTEF64),
+            "ltf64" => Some(Opcode::LTF64),
+            "ltef64" => Some(Opcode::LTEF64),
+            "shl" => Some(Opcode::SHL),
+            "shr" => Some(Opcode::SHR),
+            "and" => Some(Opcode::AND),
+            "or" => Some(Opcode::OR),
+            "xor" => Some(Opcode::XOR),
+            "not" => Some(Opcode::NOT),
+            "lui" => Some(Opcode::LUI),
+            "cloop" => Some(Opcode::CLOOP),
+            "loop" => Some(Opcode::LOOP),
+            "loadm" => Some(Opcode::LOADM),
+            "setm" => Some(Opcode::SETM),
+            "push" => Some(Opcode::PUSH),
+            "pop" => Some(Opcode::POP),
+            "call" => Some(Opcode::CALL),
+            "ret" => Some(Opcode::RET),
+            "syscall" => Some(Opcode::SYSCALL),
+            "fill" => Some(Opcode::FILL),
+            "rol" => Some(Opcode::ROL),
+            "ror" => Some(Opcode::ROR),
+            "clz" => Some(Opcode::CLZ),
+            "popcnt" => Some(Opcode::POPCNT),
+            "loadmo" => Some(Opcode::LOADMO),
+            "load32" => Some(Opcode::LOAD32),
+            "pusha" => Some(Opcode::PUSHA),
+            "popa" => Some(Opcode::POPA),
+            "rand" => Some(Opcode::RAND),
+            "time" => Some(Opcode::TIME),
+            "readi" => Some(Opcode::READI),
+            "clamp" => Some(Opcode::CLAMP),
+            "min" => Some(Opcode::MIN),
+            "max" => Some(Opcode::MAX),
+            "minf64" => Some(Opcode::MINF64),
+            "maxf64" => Some(Opcode::MAXF64),
+            "clampf64" => Some(Opcode::CLAMPF64),
+            "addi" => Some(Opcode::ADDI),
+            "tas" => Some(Opcode::TAS),
+            "copyro" => Some(Opcode::COPYRO),
+            "heapsz" => Some(Opcode::HEAPSZ),
+            "mod" => Some(Opcode::MOD),
+            "mov" => Some(Opcode::MOV),
+            "prti" => Some(Opcode::PRTI),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction sets the pc itself instead of just moving past its operands
+    pub fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Opcode::JMP | Opcode::JMPF | Opcode::JMPB | Opcode::JMPE | Opcode::CALL | Opcode::RET
+        )
+    }
+
+    /// How many bytes an instruction with this opcode takes up, opcode byte included.
+    /// Most are one 4 byte slot; LOAD32 also spans the following slot, which holds its immediate.
+    pub fn instruction_len(self) -> usize {
+        match self {
+            Opcode::LOAD32 => 8,
+            _ => 4,
         }
     }
+
+    /// How the VM reads the operands that follow the opcode byte
+    pub fn operand_layout(self) -> OperandLayout {
+        use OperandLayout::*;
+        match self {
+            Opcode::HLT
+            | Opcode::IGL
+            | Opcode::NOP
+            | Opcode::RET
+            | Opcode::PUSHA
+            | Opcode::POPA => Empty,
+            Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JMPE
+            | Opcode::CALL
+            | Opcode::INC
+            | Opcode::DEC
+            | Opcode::ALOC
+            | Opcode::HEAPSZ
+            | Opcode::RAND
+            | Opcode::TIME
+            | Opcode::READI
+            | Opcode::PUSH
+            | Opcode::POP
+            | Opcode::PRTI => Reg,
+            Opcode::EQ
+            | Opcode::NEQ
+            | Opcode::GTE
+            | Opcode::LTE
+            | Opcode::LT
+            | Opcode::GT
+            | Opcode::EQF64
+            | Opcode::NEQF64
+            | Opcode::GTF64
+            | Opcode::GTEF64
+            | Opcode::LTF64
+            | Opcode::LTEF64
+            | Opcode::NOT
+            | Opcode::CLZ
+            | Opcode::POPCNT
+            | Opcode::TAS
+            | Opcode::LOADM
+            | Opcode::SETM
+            | Opcode::MOV => RegReg,
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::MOD
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::ADDF64
+            | Opcode::SUBF64
+            | Opcode::MULF64
+            | Opcode::DIVF64
+            | Opcode::MIN
+            | Opcode::MAX
+            | Opcode::MINF64
+            | Opcode::MAXF64
+            | Opcode::CLAMP
+            | Opcode::CLAMPF64
+            | Opcode::FILL
+            | Opcode::COPYRO => RegRegReg,
+            Opcode::LOAD | Opcode::LUI | Opcode::LOADF64 | Opcode::ROL | Opcode::ROR => RegImm16,
+            Opcode::SHL | Opcode::SHR => RegImm8,
+            Opcode::ADDI | Opcode::LOADMO => RegRegImm8,
+            Opcode::LOAD32 => RegImm32,
+            Opcode::PRTS | Opcode::SYSCALL | Opcode::DJMPE | Opcode::CLOOP | Opcode::LOOP => Imm16,
+        }
+    }
+
+    /// Renders an encoded instruction, opcode byte included, back into assembly, e.g. `load $0 #100`.
+    /// Missing bytes at the end of a truncated program read as 0.
+    pub fn disassemble(self, bytes: &[u8]) -> String {
+        let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+        let imm16 = |i: usize| u16::from_be_bytes([byte(i), byte(i + 1)]);
+        let mnemonic = format!("{:?}", self).to_lowercase();
+        match self.operand_layout() {
+            OperandLayout::Empty => mnemonic,
+            OperandLayout::Reg => format!("{} ${}", mnemonic, byte(1)),
+            OperandLayout::RegReg => format!("{} ${} ${}", mnemonic, byte(1), byte(2)),
+            OperandLayout::RegRegReg => {
+                format!("{} ${} ${} ${}", mnemonic, byte(1), byte(2), byte(3))
+            },
+            OperandLayout::RegImm16 => format!("{} ${} #{}", mnemonic, byte(1), imm16(2)),
+            OperandLayout::RegImm8 => format!("{} ${} #{}", mnemonic, byte(1), byte(2)),
+            OperandLayout::RegRegImm8 => {
+                format!("{} ${} ${} #{}", mnemonic, byte(1), byte(2), byte(3) as i8)
+            },
+            OperandLayout::RegImm32 => format!(
+                "{} ${} #{}",
+                mnemonic,
+                byte(1),
+                i32::from_be_bytes([byte(4), byte(5), byte(6), byte(7)])
+            ),
+            OperandLayout::Imm16 => format!("{} #{}", mnemonic, imm16(1)),
+        }
+    }
+}
+
+/// The operands that follow an opcode byte: registers are one byte, immediates as wide as noted
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OperandLayout {
+    Empty,
+    Reg,
+    RegReg,
+    RegRegReg,
+    RegImm16,
+    RegImm8,
+    RegRegImm8,
+    RegImm32,
+    Imm16,
 }
 
 #[allow(dead_code)]
@@ -252,9 +533,69 @@ mod tests {
 
     #[test]
     fn test_str_to_opcode() {
-        let opcode = Opcode::from("hlt");
+        let opcode = Opcode::from_str_lossy("hlt");
         assert_eq!(opcode, Opcode::HLT);
-        let opcode = Opcode::from("illegal");
+        let opcode = Opcode::from_str_lossy("illegal");
         assert_eq!(opcode, Opcode::IGL);
     }
+
+    #[test]
+    fn test_try_from() {
+        assert_eq!(Opcode::try_from("load"), Ok(Opcode::LOAD));
+        assert_eq!(Opcode::try_from("igl"), Ok(Opcode::IGL));
+        assert_eq!(
+            Opcode::try_from("nonsense"),
+            Err(UnknownMnemonic {
+                mnemonic: "nonsense".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_from_str() {
+        assert_eq!(Opcode::try_from_str("load"), Some(Opcode::LOAD));
+        assert_eq!(Opcode::try_from_str("lod"), None);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(Opcode::LOAD.disassemble(&[0, 0, 0, 100]), "load $0 #100");
+        assert_eq!(Opcode::ADD.disassemble(&[1, 0, 1, 2]), "add $0 $1 $2");
+        assert_eq!(Opcode::ADDI.disassemble(&[67, 1, 1, 255]), "addi $1 $1 #-1");
+        assert_eq!(
+            Opcode::LOAD32.disassemble(&[55, 3, 0, 0, 0, 1, 17, 112]),
+            "load32 $3 #70000"
+        );
+        assert_eq!(Opcode::HLT.disassemble(&[5]), "hlt");
+    }
+
+    #[test]
+    fn test_mod_conversions() {
+        assert_eq!(Into::<u8>::into(Opcode::MOD), 71);
+        assert_eq!(Opcode::from(71), Opcode::MOD);
+        assert_eq!(Opcode::try_from("mod"), Ok(Opcode::MOD));
+        assert_eq!(Opcode::MOD.disassemble(&[71, 0, 1, 2]), "mod $0 $1 $2");
+    }
+
+    #[test]
+    fn test_mov_conversions() {
+        assert_eq!(Into::<u8>::into(Opcode::MOV), 72);
+        assert_eq!(Opcode::from(72), Opcode::MOV);
+        assert_eq!(Opcode::try_from("mov"), Ok(Opcode::MOV));
+        assert_eq!(Opcode::MOV.disassemble(&[72, 1, 0, 0]), "mov $1 $0");
+    }
+
+    #[test]
+    fn test_prti_conversions() {
+        assert_eq!(Into::<u8>::into(Opcode::PRTI), 73);
+        assert_eq!(Opcode::from(73), Opcode::PRTI);
+        assert_eq!(Opcode::try_from("prti"), Ok(Opcode::PRTI));
+        assert_eq!(Opcode::PRTI.disassemble(&[73, 2, 0, 0]), "prti $2");
+    }
+
+    #[test]
+    fn test_instruction_len() {
+        assert_eq!(Opcode::LOAD.instruction_len(), 4);
+        assert_eq!(Opcode::LOAD32.instruction_len(), 8);
+    }
 }
@@ -2,6 +2,7 @@ pub mod assembler;
 pub mod cli;
 pub mod cluster;
 pub mod instruction;
+pub mod linker;
 pub mod remote;
 pub mod repl;
 pub mod scheduler;
@@ -11,6 +12,7 @@ pub mod vm;
 extern crate byteorder;
 extern crate chrono;
 extern crate clap;
+extern crate crossterm;
 extern crate env_logger;
 extern crate log;
 extern crate nom;
@@ -0,0 +1,191 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use crate::assembler::{PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
+
+/// Which part of an object a symbol points into
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Section {
+    Code,
+    Ro,
+}
+
+/// A label an object makes available to the others it is linked with
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObjectSymbol {
+    pub name: String,
+    pub section: Section,
+    /// Offset of the label from the start of its section within this object
+    pub offset: u32,
+}
+
+/// One separately assembled piece of a program, e.g. the output of a relocatable `Assembler`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Object {
+    pub code: Vec<u8>,
+    pub ro: Vec<u8>,
+    pub symbols: Vec<ObjectSymbol>,
+    /// Places in `code` that refer to another object's label: (byte offset, label name)
+    pub relocations: Vec<(usize, String)>,
+}
+
+impl Object {
+    pub fn new(code: Vec<u8>, ro: Vec<u8>) -> Object {
+        Object {
+            code,
+            ro,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_symbol(mut self, name: &str, section: Section, offset: u32) -> Self {
+        self.symbols.push(ObjectSymbol {
+            name: name.to_string(),
+            section,
+            offset,
+        });
+        self
+    }
+
+    pub fn with_relocations(mut self, relocations: Vec<(usize, String)>) -> Self {
+        self.relocations = relocations;
+        self
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LinkError {
+    UnresolvedSymbol { name: String },
+    DuplicateSymbol { name: String },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::UnresolvedSymbol { name } => {
+                write!(f, "No object declares the symbol: {}", name)
+            },
+            LinkError::DuplicateSymbol { name } => {
+                write!(
+                    f,
+                    "The symbol is declared by more than one object: {}",
+                    name
+                )
+            },
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+/// Links the objects, in order, into one runnable program.
+///
+/// The RO sections and the code sections are each concatenated, every symbol is moved to where
+/// its section ended up, and each relocation site is patched with the symbol's final address.
+/// Code addresses are absolute pc values and RO addresses are offsets into the RO section.
+/// The result is laid out as the header, the 4 byte code offset, the RO section and then the code.
+pub fn link(objects: Vec<Object>) -> Result<Vec<u8>, LinkError> {
+    let ro_len: usize = objects.iter().map(|o| o.ro.len()).sum();
+    let code_start = PIE_HEADER_LENGTH + 4 + ro_len;
+
+    // First work out where every symbol lands
+    let mut addresses = HashMap::new();
+    let mut code_base = 0;
+    let mut ro_base = 0;
+    for object in &objects {
+        for symbol in &object.symbols {
+            let address = match symbol.section {
+                Section::Code => code_start + code_base + symbol.offset as usize,
+                Section::Ro => ro_base + symbol.offset as usize,
+            };
+            if addresses.insert(symbol.name.clone(), address).is_some() {
+                return Err(LinkError::DuplicateSymbol {
+                    name: symbol.name.clone(),
+                });
+            }
+        }
+        code_base += object.code.len();
+        ro_base += object.ro.len();
+    }
+
+    // Then glue the sections together, patching each object's relocations as it is copied
+    let mut ro = Vec::with_capacity(ro_len);
+    let mut code = vec![];
+    for object in objects {
+        let base = code.len();
+        code.extend_from_slice(&object.code);
+        for (offset, name) in &object.relocations {
+            let address = match addresses.get(name) {
+                Some(address) => *address,
+                None => return Err(LinkError::UnresolvedSymbol { name: name.clone() }),
+            };
+            // Label operands are 16 bit big endian, like every other operand
+            code[base + offset..base + offset + 2].copy_from_slice(&(address as u16).to_be_bytes());
+        }
+        ro.extend_from_slice(&object.ro);
+    }
+
+    let mut program = PIE_HEADER_PREFIX.to_vec();
+    program.extend_from_slice(&(ro_len as u32).to_be_bytes());
+    program.resize(PIE_HEADER_LENGTH, 0);
+    // The VM starts executing this many bytes after the code offset field
+    program.extend_from_slice(&(ro_len as u32).to_be_bytes());
+    program.append(&mut ro);
+    program.append(&mut code);
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::Assembler, vm::VM};
+
+    use super::{link, LinkError, Object, Section};
+
+    #[test]
+    fn test_link_call_across_objects() {
+        let mut main_asm = Assembler::new().with_relocatable(true);
+        let main_bytes = main_asm
+            .assemble(".data\n.code\nload $1 @add_ten\njmp $1\n")
+            .unwrap();
+        let main = Object::new(main_asm.code_section(&main_bytes).to_vec(), vec![])
+            .with_relocations(main_asm.relocations().to_vec());
+
+        let mut lib_asm = Assembler::new();
+        let lib_bytes = lib_asm
+            .assemble(".data\ngreeting: .asciiz 'Hi'\n.code\nload $2 #10\nadd $0 $2 $0\nhlt\n")
+            .unwrap();
+        // Code label offsets are absolute within a single program, so the object's entry point is declared here
+        let lib = Object::new(
+            lib_asm.code_section(&lib_bytes).to_vec(),
+            lib_asm.ro_section().to_vec(),
+        )
+        .with_symbol("add_ten", Section::Code, 0);
+
+        let program = link(vec![main, lib]).unwrap();
+        let mut vm = VM::new();
+        vm.registers[0] = 5;
+        vm.add_bytes(program);
+        vm.run();
+        assert_eq!(vm.registers[0], 15);
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let caller = Object::new(vec![0, 1, 0, 0], vec![])
+            .with_relocations(vec![(2, "missing".to_string())]);
+        assert_eq!(
+            link(vec![caller]),
+            Err(LinkError::UnresolvedSymbol {
+                name: "missing".to_string()
+            })
+        );
+
+        let first = Object::new(vec![5, 0, 0, 0], vec![]).with_symbol("func", Section::Code, 0);
+        let second = first.clone();
+        assert_eq!(
+            link(vec![first, second]),
+            Err(LinkError::DuplicateSymbol {
+                name: "func".to_string()
+            })
+        );
+    }
+}
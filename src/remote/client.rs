@@ -1,15 +1,27 @@
-use std::io::{BufRead, BufWriter, Write};
-use std::thread;
-use std::{io::BufReader, net::TcpStream};
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::{
+    io::BufReader,
+    net::{Shutdown, TcpStream},
+};
+use std::{thread, time::Duration};
 
 use crate::repl::{self};
 use crate::vm::VM;
 
+pub static AUTH_CHALLENGE: &str = "Token: ";
+pub static AUTH_FAILED: &str = "Authentication failed";
+/// Longest token a client may send; a longer line is rejected without being read in full
+pub const MAX_TOKEN_LEN: usize = 256;
+/// How long a client has to answer the challenge
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Client {
     reader: BufReader<TcpStream>,
     writer: BufWriter<TcpStream>,
     raw_stream: TcpStream,
     repl: repl::REPL,
+    /// 设置后, 客户端必须先发送这个 token 才能使用 REPL
+    token: Option<String>,
 }
 
 impl Client {
@@ -23,7 +35,52 @@ impl Client {
             writer: { BufWriter::new(writer) },
             raw_stream,
             repl,
+            token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Sends the challenge and checks the line that comes back against the token.
+    /// Always passes when no token is set.
+    /// 未认证的客户端最多只能发送 MAX_TOKEN_LEN 字节, 并且必须在 AUTH_TIMEOUT 内回答
+    fn authenticate(&mut self) -> bool {
+        let token = match self.token.take() {
+            Some(token) => token,
+            None => return true,
+        };
+        if !self.w(AUTH_CHALLENGE) {
+            return false;
+        }
+        if self
+            .raw_stream
+            .set_read_timeout(Some(AUTH_TIMEOUT))
+            .is_err()
+        {
+            return false;
+        }
+        // Room for the token and a \r\n; a line that doesn't end within it is too long
+        let mut reply = String::new();
+        let limit = (MAX_TOKEN_LEN + 2) as u64;
+        match self.reader.by_ref().take(limit).read_line(&mut reply) {
+            Ok(_) if reply.ends_with('\n') => {},
+            _ => return false,
         }
+        if self.raw_stream.set_read_timeout(None).is_err() {
+            return false;
+        }
+        // Compare every byte so the time taken doesn't give away how much of the token matched
+        let reply = reply.trim_end().as_bytes();
+        let token = token.as_bytes();
+        reply.len() == token.len()
+            && reply
+                .iter()
+                .zip(token)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
     }
 
     fn recv_loop(&mut self) {
@@ -32,24 +89,20 @@ impl Client {
         let mut writer = self.raw_stream.try_clone().unwrap();
         let _t = thread::spawn(move || {
             let channel = rx.unwrap();
-            loop {
-                match channel.recv() {
-                    Ok(msg) => {
-                        let _ = writer.write_all(msg.as_bytes());
-                        let _ = writer.flush();
-                    },
-                    Err(e) => {
-                        let _ = writer.write_all(
-                            format!("Unable to recieve data in recv_loop: {:?}", e).as_bytes(),
-                        );
-                        let _ = writer.flush();
-                    },
-                }
+            // Ends once the REPL is gone, i.e. the session is over
+            while let Ok(msg) = channel.recv() {
+                let _ = writer.write_all(msg.as_bytes());
+                let _ = writer.flush();
             }
         });
     }
 
     pub fn run(&mut self) {
+        if !self.authenticate() {
+            self.w(&format!("{}\n", AUTH_FAILED));
+            let _ = self.raw_stream.shutdown(Shutdown::Both);
+            return;
+        }
         self.recv_loop();
         // let mut buf = String::new();  // remote msg will be accumulated, and only the top first msg will be handled
         let banner = format!("{}\n{}", repl::REMOTE_BANNER, repl::PROMPT);
@@ -57,11 +110,14 @@ impl Client {
         loop {
             let mut buf = String::new();
             match self.reader.read_line(&mut buf) {
+                // The other end closed the connection
+                Ok(0) => break,
                 Ok(_) => {
                     self.repl.run_single(&buf.trim_end());
                 },
                 Err(e) => {
                     eprintln!("Error receiving: {:#?}", e);
+                    break;
                 },
             }
         }
@@ -1,10 +1,19 @@
-use std::{net::TcpListener, thread};
+use std::{
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
 
-use crate::remote::client::Client;
+use crate::{remote::client::Client, util::net};
 
 pub struct Server {
     bind_hostname: String,
     bind_port: String,
+    shutdown: Arc<AtomicBool>,
+    token: Option<String>,
 }
 
 impl Server {
@@ -12,22 +21,154 @@ impl Server {
         Server {
             bind_hostname,
             bind_port,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            token: None,
         }
     }
 
-    pub fn listen(&mut self) {
+    /// Makes clients send `token` before they are given a REPL. Clients can send at most
+    /// `MAX_TOKEN_LEN` bytes, so a longer token can never be matched.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Serves remote REPL clients until `shutdown` is called
+    pub fn listen(&self) {
         println!("Initializing TCP Server...");
 
         let address = format!("{}:{}", self.bind_hostname, self.bind_port);
         let listener = TcpListener::bind(address).unwrap();
 
-        for stream in listener.incoming() {
+        for stream in net::incoming_until(&listener, &self.shutdown).unwrap() {
             if let Ok(s) = stream {
-                thread::spawn(|| {
-                    let mut client = Client::new(s);
+                let token = self.token.clone();
+                thread::spawn(move || {
+                    let mut client = Client::new(s).with_token(token);
                     client.run();
                 });
             }
         }
     }
+
+    /// Makes `listen` stop accepting clients and return. Clients already connected keep
+    /// their session.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            mpsc::{self, Receiver},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        remote::client::{AUTH_CHALLENGE, AUTH_FAILED, MAX_TOKEN_LEN},
+        repl::REMOTE_BANNER,
+    };
+
+    use super::Server;
+
+    /// Starts `server` on a free port. The receiver gets a message once `listen` returns.
+    fn start(server: Server) -> (Arc<Server>, u16, Receiver<()>) {
+        // Find a free port, then give it to the server
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server = Arc::new(Server {
+            bind_port: port.to_string(),
+            ..server
+        });
+        let (done_tx, done_rx) = mpsc::channel();
+        let listening = server.clone();
+        thread::spawn(move || {
+            listening.listen();
+            done_tx.send(()).unwrap();
+        });
+        (server, port, done_rx)
+    }
+
+    fn connect(port: u16) -> TcpStream {
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)) {
+                return stream;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("server never started");
+    }
+
+    #[test]
+    fn test_shutdown_stops_listen() {
+        let (server, port, done_rx) = start(Server::new("127.0.0.1".to_string(), String::new()));
+
+        let mut reader = BufReader::new(connect(port));
+        let mut banner = String::new();
+        reader.read_line(&mut banner).unwrap();
+        assert_eq!(banner.trim_end(), REMOTE_BANNER);
+
+        server.shutdown();
+        assert!(done_rx.recv_timeout(Duration::from_secs(2)).is_ok());
+    }
+
+    #[test]
+    fn test_token_required() {
+        let server =
+            Server::new("127.0.0.1".to_string(), String::new()).with_token("s3cret".to_string());
+        let (server, port, _) = start(server);
+
+        // Right token, gets the banner
+        let mut stream = connect(port);
+        let mut challenge = vec![0; AUTH_CHALLENGE.len()];
+        stream.read_exact(&mut challenge).unwrap();
+        assert_eq!(challenge, AUTH_CHALLENGE.as_bytes());
+        stream.write_all(b"s3cret\n").unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut banner = String::new();
+        reader.read_line(&mut banner).unwrap();
+        assert_eq!(banner.trim_end(), REMOTE_BANNER);
+
+        // Wrong token, turned away and the connection is closed
+        let mut stream = connect(port);
+        let mut challenge = vec![0; AUTH_CHALLENGE.len()];
+        stream.read_exact(&mut challenge).unwrap();
+        stream.write_all(b"s3cre\n").unwrap();
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest.trim_end(), AUTH_FAILED);
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_oversized_token_rejected() {
+        let server =
+            Server::new("127.0.0.1".to_string(), String::new()).with_token("s3cret".to_string());
+        let (server, port, _) = start(server);
+
+        // A line longer than any token, with the real token at its end. The server gives up
+        // after MAX_TOKEN_LEN + 2 bytes instead of buffering the whole line.
+        let mut stream = connect(port);
+        let mut challenge = vec![0; AUTH_CHALLENGE.len()];
+        stream.read_exact(&mut challenge).unwrap();
+        let mut line = vec![b' '; MAX_TOKEN_LEN + 2];
+        line.extend_from_slice(b"s3cret\n");
+        stream.write_all(&line).unwrap();
+        let mut failed = vec![0; AUTH_FAILED.len()];
+        stream.read_exact(&mut failed).unwrap();
+        assert_eq!(failed, AUTH_FAILED.as_bytes());
+
+        server.shutdown();
+    }
 }
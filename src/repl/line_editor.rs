@@ -0,0 +1,88 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crossterm::{
+    cursor::MoveToColumn,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    terminal::{self, Clear, ClearType},
+};
+
+use super::{CommandManager, PROMPT};
+
+/// Reads one line from the terminal, letting up/down walk through the commands in `history`.
+/// When stdin isn't a terminal, e.g. input piped in from a file, it falls back to a plain read.
+/// Returns `None` at the end of input.
+pub fn read_line(history: &mut CommandManager) -> io::Result<Option<String>> {
+    if !io::stdin().is_terminal() {
+        let mut line = String::new();
+        return match io::stdin().lock().read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        };
+    }
+
+    terminal::enable_raw_mode()?;
+    let line = edit_line(history);
+    terminal::disable_raw_mode()?;
+    // Raw mode doesn't turn the Enter key into a new line, so finish the line here
+    println!();
+    line
+}
+
+fn edit_line(history: &mut CommandManager) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    loop {
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        match key {
+            KeyEvent {
+                code: KeyCode::Char('c' | 'd'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => return Ok(None),
+            KeyEvent {
+                code: KeyCode::Enter,
+                ..
+            } => {
+                history.reset_offset();
+                return Ok(Some(line));
+            },
+            KeyEvent {
+                code: KeyCode::Up, ..
+            } => {
+                if let Some(command) = history.last_command() {
+                    line = command;
+                }
+            },
+            KeyEvent {
+                code: KeyCode::Down,
+                ..
+            } => {
+                // Going past the newest command leaves an empty line to type into
+                line = history.next_command().unwrap_or_default();
+            },
+            KeyEvent {
+                code: KeyCode::Backspace,
+                ..
+            } => {
+                line.pop();
+            },
+            KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            } => line.push(c),
+            _ => continue,
+        }
+        redraw(&line)?;
+    }
+}
+
+/// Replaces the current terminal line with the prompt followed by `line`
+fn redraw(line: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    queue!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+    write!(stdout, "{}{}", PROMPT, line)?;
+    stdout.flush()
+}
@@ -1,23 +1,91 @@
 pub mod command_parser;
+pub mod line_editor;
 
 use command_parser::CommandParser;
 
-use crate::assembler::program_parser::program;
-use crate::assembler::Assembler;
+use crate::assembler::{
+    Assembler, PIE_HEADER_LENGTH, PIE_HEADER_PREFIX, PIE_HEADER_VERSION_OFFSET,
+};
 use crate::cluster;
+use crate::instruction::Opcode;
 use crate::scheduler::Scheduler;
 use crate::util::display;
-use crate::vm::VM;
+use crate::vm::{RunOutcome, VM};
 
 use std::io::Write;
 use std::io::{self};
 use std::net::TcpStream;
 use std::num::ParseIntError;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Instant;
 use std::{self, vec};
 
 const COMMAND_PREFIX: char = '!';
 
+/// Every REPL command with a one-line description, as listed by `!help`
+const COMMANDS: [(&str, &str); 27] = [
+    ("!help", "List the available commands"),
+    ("!quit", "Leave the REPL"),
+    ("!history", "Show the lines entered so far"),
+    ("!program", "Show the bytes in the VM's program vector"),
+    (
+        "!disassemble",
+        "Show the program decoded back into assembly",
+    ),
+    (
+        "!clear",
+        "Clear the program, registers or history, e.g. !clear program",
+    ),
+    (
+        "!reset",
+        "Clear the program, registers, memory and symbols in one go",
+    ),
+    (
+        "!registers",
+        "Show the registers, or !registers float for the float ones",
+    ),
+    ("!float_registers", "Show the floating point registers"),
+    ("!symbols", "Show the assembler's symbol table"),
+    (
+        "!step",
+        "Execute one instruction and show the pc and registers",
+    ),
+    (
+        "!continue",
+        "Run until the program stops or reaches a breakpoint",
+    ),
+    (
+        "!breakpoint",
+        "Pause at an instruction offset, e.g. !breakpoint 72",
+    ),
+    ("!watch", "Show a register after every step, e.g. !watch $0"),
+    (
+        "!time",
+        "Run the program from the start and report how long it took",
+    ),
+    ("!opcodes", "List which opcodes the VM implements"),
+    ("!find", "List where an opcode is used, e.g. !find add"),
+    ("!header", "Show the fields of the program's header"),
+    (
+        "!export",
+        "Write the assembly typed in this session to a file",
+    ),
+    ("!save", "Write the program's bytes to a file"),
+    ("!load_file", "Load assembly from a file into the program"),
+    ("!spawn", "Assemble a file and run it"),
+    ("!ps", "List the VMs started with !spawn"),
+    (
+        "!join",
+        "Wait for spawned VMs to finish and show their events",
+    ),
+    ("!start_cluster", "Start listening for other VMs to join"),
+    (
+        "!join_cluster",
+        "Join another VM's cluster, e.g. !join_cluster 127.0.0.1 2254",
+    ),
+    ("!cluster_members", "List the VMs in the cluster"),
+];
+
 pub static REMOTE_BANNER: &'static str = "Welcome to lrvm! Let's be productive.";
 pub static PROMPT: &'static str = ">>> ";
 
@@ -35,31 +103,38 @@ impl CommandManager {
         }
     }
 
+    /// Adds a command to the history and moves the cursor back past the newest entry
     pub fn push(&mut self, command: String) {
         self.command_buffer.push(command);
-        self.offset += 1;
+        self.reset_offset();
     }
 
-    pub fn last_command(&mut self) -> String {
-        if self.offset == 0 {
-            self.currnet_command()
-        } else {
+    /// Moves the cursor one command back (the up key), stopping at the oldest one.
+    /// Returns `None` if there is no history yet.
+    pub fn last_command(&mut self) -> Option<String> {
+        if self.offset > 0 {
             self.offset -= 1;
-            self.currnet_command()
         }
+        self.currnet_command()
     }
 
-    pub fn currnet_command(&self) -> String {
-        self.command_buffer[self.offset - 1].clone()
+    /// The command under the cursor, or `None` when the cursor is past the newest entry
+    pub fn currnet_command(&self) -> Option<String> {
+        self.command_buffer.get(self.offset).cloned()
     }
 
-    pub fn next_command(&mut self) -> String {
-        if self.offset == self.command_buffer.len() {
-            self.currnet_command()
-        } else {
+    /// Moves the cursor one command forward (the down key). Past the newest command there is
+    /// nothing to recall, so this returns `None`.
+    pub fn next_command(&mut self) -> Option<String> {
+        if self.offset < self.command_buffer.len() {
             self.offset += 1;
-            self.currnet_command()
         }
+        self.currnet_command()
+    }
+
+    /// Moves the cursor past the newest entry, where a fresh line is typed
+    pub fn reset_offset(&mut self) {
+        self.offset = self.command_buffer.len();
     }
 
     pub fn clear_all(&mut self) {
@@ -74,6 +149,8 @@ pub struct REPL {
     vm: VM,
     asm: Assembler,
     scheduler: Scheduler,
+    /// 被 `!watch` 的寄存器, 以及上一次报告时的值, 用来判断是否改变
+    watches: Vec<(usize, i32)>,
     pub tx_pipe: Option<Box<Sender<String>>>,
     pub rx_pipe: Option<Box<Receiver<String>>>,
 }
@@ -87,6 +164,7 @@ impl REPL {
             vm,
             asm: Assembler::new(),
             scheduler: Scheduler::new(),
+            watches: vec![],
             tx_pipe: { Some(Box::new(tx)) },
             rx_pipe: { Some(Box::new(rx)) },
         }
@@ -115,29 +193,41 @@ impl REPL {
     }
 
     pub fn run_single(&mut self, buffer: &str) -> Option<String> {
+        self.command_manager.push(String::from(buffer.trim()));
+
         if buffer.starts_with(COMMAND_PREFIX) {
             self.execute_command(&buffer);
-            None
         } else {
-            let program = match program(&buffer) {
-                Ok((_reminder, program)) => Some(program),
-                Err(e) => {
-                    self.send_message(&format!("[Error]: Unable to parse input: {:?}", e));
-                    self.send_prompt();
-                    None
-                },
-            };
-            match program {
-                Some(p) => {
-                    let mut bytes = p.to_bytes(&self.asm.symbols);
-                    self.vm.program.append(&mut bytes);
-                    self.vm.run_once();
-                    self.send_prompt();
-                    None
-                },
-                None => None,
+            self.run_instruction(buffer);
+        }
+        None
+    }
+
+    /// Assembles a typed instruction, runs it straight away and reports any watched registers
+    fn run_instruction(&mut self, buffer: &str) {
+        let program = match self.asm.parse(buffer) {
+            Ok((_reminder, program)) => program,
+            Err(e) => {
+                self.send_message(&format!("[Error]: Unable to parse input: {:?}", e));
+                self.send_prompt();
+                return;
+            },
+        };
+        for (index, instruction) in program.instructions.iter().enumerate() {
+            if let Some(error) = instruction.third_immediate_error(&self.asm.symbols, index as u32)
+            {
+                self.send_message(&format!("[Error]: {}", error));
+                self.send_prompt();
+                return;
             }
         }
+
+        self.vm
+            .program
+            .append(&mut program.to_bytes(&self.asm.symbols));
+        self.vm.run_once();
+        self.send_watches();
+        self.send_prompt();
     }
 
     pub fn run(&mut self) {
@@ -147,13 +237,13 @@ impl REPL {
         self.send_prompt();
 
         loop {
-            let mut buffer = String::new();
-
-            let stdin = io::stdin();
-
-            stdin
-                .read_line(&mut buffer)
-                .expect("[Error]: Unable to read line from user");
+            let buffer = match line_editor::read_line(&mut self.command_manager)
+                .expect("[Error]: Unable to read line from user")
+            {
+                Some(buffer) => buffer,
+                // Ctrl-C, Ctrl-D or the end of piped input
+                None => self.quit(&[]),
+            };
 
             let history_copy = String::from(buffer.trim());
 
@@ -162,21 +252,7 @@ impl REPL {
             if buffer.starts_with(COMMAND_PREFIX) {
                 self.execute_command(&buffer);
             } else {
-                let program = match program(&buffer) {
-                    Ok((_reminder, program)) => program,
-                    Err(e) => {
-                        self.send_message(&format!("Unable to parse input: {:?}", e));
-                        self.send_prompt();
-                        continue;
-                    },
-                };
-
-                self.vm
-                    .program
-                    .append(&mut program.to_bytes(&self.asm.symbols));
-
-                self.vm.run_once();
-                self.send_prompt();
+                self.run_instruction(&buffer);
             }
         }
     }
@@ -208,15 +284,29 @@ impl REPL {
     fn execute_command(&mut self, input: &str) {
         let args = CommandParser::tokenize(input);
         match args[0] {
+            "!help" => self.help(&args[1..]),
             "!quit" => self.quit(&args[1..]),
             "!history" => self.history(&args[1..]),
             "!program" => self.program(&args[1..]),
             "!clear" => self.clear(&args[1..]),
+            "!reset" => self.reset(&args[1..]),
+            "!step" => self.step(&args[1..]),
+            "!continue" => self.continue_execution(&args[1..]),
+            "!breakpoint" => self.breakpoint(&args[1..]),
+            "!watch" => self.watch(&args[1..]),
             "!registers" => self.registers(&args[1..]),
+            "!float_registers" => self.float_registers(&args[1..]),
             "!symbols" => self.symbols(&args[1..]),
             "!start_cluster" => self.start_cluster(&args[1..]),
             "!join_cluster" => self.join_cluster(&args[1..]),
             "!cluster_members" => self.cluster_members(&args[1..]),
+            "!time" => self.time(&args[1..]),
+            "!opcodes" => self.opcodes(&args[1..]),
+            "!export" => self.export(&args[1..]),
+            "!save" => self.save(&args[1..]),
+            "!find" => self.find(&args[1..]),
+            "!header" => self.header(&args[1..]),
+            "!disassemble" => self.disassemble(&args[1..]),
             "!load_file" => {
                 let contents;
 
@@ -261,14 +351,28 @@ impl REPL {
 
                 self.spawn(&args[1..], &contents);
             },
+            "!ps" => self.ps(&args[1..]),
+            "!join" => self.join(&args[1..]),
             _ => {
                 self.send_message(&format!("Invalid command!: {}", args[0]));
+                self.send_message("Type !help to see the available commands");
                 self.send_prompt();
             },
         }
     }
 
-    fn quit(&mut self, _args: &[&str]) {
+    fn help(&mut self, _args: &[&str]) {
+        self.send_message("Available commands:");
+        for (name, description) in COMMANDS {
+            self.send_message(&format!("  {:<18}{}", name, description));
+        }
+        self.send_prompt();
+    }
+
+    fn quit(&mut self, _args: &[&str]) -> ! {
+        if let Ok(mut lock) = self.vm.connection_manager.write() {
+            lock.disconnect_all();
+        }
         self.send_message("Farewell! Have a great day!");
         std::process::exit(0);
     }
@@ -319,6 +423,154 @@ impl REPL {
         self.send_prompt();
     }
 
+    /// Executes the instruction at the pc and shows where that left the VM
+    fn step(&mut self, _args: &[&str]) {
+        if self.vm.program.is_empty() {
+            self.send_message("No program loaded, nothing to step through");
+            self.send_prompt();
+            return;
+        }
+        let stopped = self.vm.step();
+        self.send_watches();
+        self.send_state(stopped);
+    }
+
+    /// Runs from the pc until the program stops or reaches a breakpoint
+    fn continue_execution(&mut self, _args: &[&str]) {
+        if self.vm.program.is_empty() {
+            self.send_message("No program loaded, nothing to continue");
+            self.send_prompt();
+            return;
+        }
+        match self.vm.resume() {
+            RunOutcome::Paused => {
+                self.send_message(&format!("Paused at breakpoint {}", self.vm.pc()));
+                self.send_state(false);
+            },
+            _ => self.send_state(true),
+        }
+    }
+
+    /// Sets a breakpoint at an instruction offset, or removes it with `!breakpoint remove <addr>`
+    fn breakpoint(&mut self, args: &[&str]) {
+        let (remove, addr) = match args {
+            ["remove", addr] => (true, addr.parse::<usize>()),
+            [addr] => (false, addr.parse::<usize>()),
+            _ => {
+                self.send_message(
+                    "[Error]: Please give an instruction offset, e.g. !breakpoint 72",
+                );
+                self.send_prompt();
+                return;
+            },
+        };
+        let addr = match addr {
+            Ok(addr) => addr,
+            Err(e) => {
+                self.send_message(&format!("[Error]: Invalid breakpoint offset: {}", e));
+                self.send_prompt();
+                return;
+            },
+        };
+        if !remove {
+            self.vm.add_breakpoint(addr);
+            self.send_message(&format!("Breakpoint set at {}", addr));
+        } else if self.vm.remove_breakpoint(addr) {
+            self.send_message(&format!("Breakpoint at {} removed", addr));
+        } else {
+            self.send_message(&format!("No breakpoint at {}", addr));
+        }
+        self.send_prompt();
+    }
+
+    /// `!watch $N` adds a register to the watch list, `!watch remove $N` takes it off again
+    /// and a bare `!watch` lists what is watched
+    fn watch(&mut self, args: &[&str]) {
+        let (remove, register) = match args {
+            [] => {
+                if self.watches.is_empty() {
+                    self.send_message("No registers are watched");
+                }
+                for (register, value) in self.watches.clone() {
+                    self.send_message(&format!("${} = {}", register, value));
+                }
+                self.send_prompt();
+                return;
+            },
+            ["remove", register] => (true, register),
+            [register] => (false, register),
+            _ => {
+                self.send_message("[Error]: Please give a register to watch, e.g. !watch $0");
+                self.send_prompt();
+                return;
+            },
+        };
+        let register = match register
+            .strip_prefix('$')
+            .unwrap_or(register)
+            .parse::<usize>()
+        {
+            Ok(register) if register < self.vm.registers.len() => register,
+            _ => {
+                self.send_message(&format!("[Error]: Invalid register: {}", register));
+                self.send_prompt();
+                return;
+            },
+        };
+        let watched = self.watches.iter().position(|(r, _)| *r == register);
+        match (remove, watched) {
+            (false, None) => {
+                self.watches.push((register, self.vm.registers[register]));
+                self.send_message(&format!("Watching ${}", register));
+            },
+            (false, Some(_)) => self.send_message(&format!("${} is already watched", register)),
+            (true, Some(index)) => {
+                self.watches.remove(index);
+                self.send_message(&format!("Stopped watching ${}", register));
+            },
+            (true, None) => self.send_message(&format!("${} is not watched", register)),
+        }
+        self.send_prompt();
+    }
+
+    /// Shows each watched register after an instruction ran, noting the ones it changed
+    fn send_watches(&mut self) {
+        for i in 0..self.watches.len() {
+            let (register, old) = self.watches[i];
+            let value = self.vm.registers[register];
+            let message = if value == old {
+                format!("watch ${} = {}", register, value)
+            } else {
+                format!("watch ${} = {} (was {})", register, value, old)
+            };
+            self.watches[i].1 = value;
+            self.send_message(&message);
+        }
+    }
+
+    /// Reports the pc and registers after `!step` or `!continue`
+    fn send_state(&mut self, stopped: bool) {
+        if stopped {
+            self.send_message(&format!("Program stopped at pc {}", self.vm.pc()));
+        } else {
+            self.send_message(&format!("pc: {}", self.vm.pc()));
+        }
+        self.send_message(&format!("registers: {:?}", self.vm.registers));
+        self.send_prompt();
+    }
+
+    /// Clears the program, registers, memory and symbols in one go, for starting an experiment over
+    fn reset(&mut self, _args: &[&str]) {
+        self.vm.reset();
+        self.asm = Assembler::new();
+        // Keep watching, but don't report the reset as a change on the next step
+        for watch in &mut self.watches {
+            watch.1 = self.vm.registers[watch.0];
+        }
+        self.send_message("VM and assembler reset");
+        self.send_prompt();
+    }
+
     fn symbols(&mut self, _args: &[&str]) {
         let mut results = vec![];
         for symbol in &self.asm.symbols.symbols {
@@ -329,7 +581,11 @@ impl REPL {
         self.send_message("End of Symbols Listing");
         self.send_prompt();
     }
-    fn registers(&mut self, _args: &[&str]) {
+    fn registers(&mut self, args: &[&str]) {
+        if args.first() == Some(&"float") {
+            self.float_registers(&args[1..]);
+            return;
+        }
         self.send_message("Listing registers and all contents:");
         let mut results = vec![];
         for register in &self.vm.registers {
@@ -340,9 +596,208 @@ impl REPL {
         self.send_prompt();
     }
 
+    fn float_registers(&mut self, _args: &[&str]) {
+        self.send_message("Listing float registers and all contents:");
+        let mut results = vec![];
+        for register in &self.vm.float_registers {
+            results.push(*register);
+        }
+        self.send_message(&format!("{:#?}", results));
+        self.send_message("End of Float Register Listing");
+        self.send_prompt();
+    }
+
+    fn opcodes(&mut self, _args: &[&str]) {
+        self.send_message("Implemented opcodes:");
+        self.send_message(&format!("{:?}", VM::implemented_opcodes()));
+        self.send_message("Unimplemented opcodes:");
+        self.send_message(&format!("{:?}", VM::unimplemented_opcodes()));
+        self.send_prompt();
+    }
+
+    /// Prints the program vector decoded back into assembly, skipping over the header and
+    /// read-only section if there is one
+    fn disassemble(&mut self, _args: &[&str]) {
+        if self.vm.program.is_empty() {
+            self.send_message("No program loaded");
+            self.send_prompt();
+            return;
+        }
+        let listing = self.vm.disassemble();
+        if let Some(&(entry, _)) = listing.first() {
+            if entry > 0 {
+                self.send_message(&format!("Header and read-only data: {} bytes", entry));
+            }
+        }
+        for (offset, text) in listing {
+            self.send_message(&format!("{:>6}: {}", offset, text));
+        }
+        self.send_prompt();
+    }
+
+    fn header(&mut self, _args: &[&str]) {
+        let header = match self.vm.header() {
+            Some(header) => header,
+            None => {
+                self.send_message("The program is too short to have a header");
+                self.send_prompt();
+                return;
+            },
+        };
+        let field =
+            |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+        let magic = &header[0..4];
+        let mut lines = vec![format!(
+            "Magic: {:?} ({})",
+            magic,
+            if magic == PIE_HEADER_PREFIX {
+                "valid"
+            } else {
+                "invalid"
+            }
+        )];
+        lines.push(format!("RO section length: {}", field(4)));
+        lines.push(format!(
+            "Required VM version: {}",
+            field(PIE_HEADER_VERSION_OFFSET)
+        ));
+        // The code offset lives just past the header, so it only exists if the program is long enough
+        if let Some(bytes) = self
+            .vm
+            .program
+            .get(PIE_HEADER_LENGTH..PIE_HEADER_LENGTH + 4)
+        {
+            lines.push(format!(
+                "Code offset: {}",
+                u32::from_be_bytes(bytes.try_into().unwrap())
+            ));
+        }
+        for line in lines {
+            self.send_message(&line);
+        }
+        self.send_prompt();
+    }
+
+    fn time(&mut self, _args: &[&str]) {
+        if self.vm.program.is_empty() {
+            self.send_message("No program loaded, nothing to time");
+            self.send_prompt();
+            return;
+        }
+
+        self.vm.rewind();
+        let start = Instant::now();
+        self.vm.run_to_completion();
+        let elapsed = start.elapsed();
+        self.send_message(&format!(
+            "Executed {} instructions in {:?}",
+            self.vm.instruction_count(),
+            elapsed
+        ));
+        self.send_prompt();
+    }
+
+    /// Lists the offsets of every instruction in the program that uses the given mnemonic
+    fn find(&mut self, args: &[&str]) {
+        let mnemonic = match args.first() {
+            Some(mnemonic) => mnemonic.to_lowercase(),
+            None => {
+                self.send_message("[Error]: Please give an opcode to look for, e.g. !find add");
+                self.send_prompt();
+                return;
+            },
+        };
+        let target = match Opcode::try_from(mnemonic.as_str()) {
+            Ok(target) => target,
+            Err(e) => {
+                self.send_message(&format!("[Error]: {}", e));
+                self.send_prompt();
+                return;
+            },
+        };
+
+        let offsets: Vec<usize> = self
+            .vm
+            .instructions()
+            .into_iter()
+            .filter(|(_, opcode)| *opcode == target)
+            .map(|(offset, _)| offset)
+            .collect();
+        if offsets.is_empty() {
+            self.send_message(&format!("{} not found in the program", mnemonic));
+        } else {
+            self.send_message(&format!("Found {} at offsets: {:?}", mnemonic, offsets));
+        }
+        self.send_prompt();
+    }
+
+    /// Writes the assembly typed in this session out as an .iasm file, adding the section
+    /// headers the assembler needs if they weren't typed
+    fn export(&mut self, args: &[&str]) {
+        let path = match utils::aggreate_path(args) {
+            Some(path) => path,
+            None => {
+                self.send_message(
+                    "[Error]: Please give a path to export to, e.g. !export session.iasm",
+                );
+                self.send_prompt();
+                return;
+            },
+        };
+
+        let lines: Vec<&str> = self
+            .command_manager
+            .command_buffer
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with(COMMAND_PREFIX))
+            .collect();
+        let mut source = String::new();
+        if !lines.contains(&".code") {
+            source.push_str(".data\n.code\n");
+        }
+        for line in lines {
+            source.push_str(line);
+            source.push('\n');
+        }
+
+        match utils::write_data_to_path(&path, &source) {
+            Ok(()) => self.send_message(&format!("Exported session to {}", path.display())),
+            Err(e) => self.send_message(&format!("[Error]: Unable to export session: {}", e)),
+        }
+        self.send_prompt();
+    }
+
+    /// Writes the VM's program vector, header included, to a bytecode file that can be run again
+    /// without reassembling
+    fn save(&mut self, args: &[&str]) {
+        let path = match utils::aggreate_path(args) {
+            Some(path) => path,
+            None => {
+                self.send_message(
+                    "[Error]: Please give a path to save to, e.g. !save program.lrvm",
+                );
+                self.send_prompt();
+                return;
+            },
+        };
+        let existed = utils::is_valid_path(&path).is_some();
+
+        match utils::write_data_to_path(&path, &self.vm.program) {
+            Ok(()) => self.send_message(&format!(
+                "{} {} bytes to {}",
+                if existed { "Overwrote with" } else { "Saved" },
+                self.vm.program.len(),
+                path.display()
+            )),
+            Err(e) => self.send_message(&format!("[Error]: Unable to save program: {}", e)),
+        }
+        self.send_prompt();
+    }
+
     fn load_file(&mut self, _args: &[&str], data_from_file: &Option<String>) {
         if let Some(contents) = data_from_file {
-            let program = match program(&contents) {
+            let program = match self.asm.parse(contents) {
                 Ok((_reminder, program)) => program,
                 Err(e) => {
                     self.send_message(&format!("[Error]: Unable to parse input: {:?}", e));
@@ -360,10 +815,21 @@ impl REPL {
         if let Some(contents) = data_from_file {
             match self.asm.assemble(&contents) {
                 Ok(mut assembled_program) => {
+                    for warning in self.asm.warnings.clone() {
+                        self.send_message(&format!("Warning: {}", warning));
+                    }
                     // println!("Sending assembled program to VM");
                     self.vm.program.append(&mut assembled_program);
                     // println!("{:#?}", self.vm.program);
-                    self.scheduler.get_thread(self.vm.clone());
+                    match self.scheduler.get_thread(self.vm.clone()) {
+                        Some(pid) => {
+                            self.send_message(&format!("Spawned process with PID {}", pid))
+                        },
+                        None => self.send_message(&format!(
+                            "No free PID below {}, use !join to reclaim finished processes",
+                            self.scheduler.get_max_pid()
+                        )),
+                    }
                 },
                 Err(errors) => {
                     for error in errors {
@@ -375,6 +841,50 @@ impl REPL {
         }
     }
 
+    fn ps(&mut self, _args: &[&str]) {
+        let processes = self.scheduler.process_infos();
+        if processes.is_empty() {
+            self.send_message("No processes have been spawned");
+            self.send_prompt();
+            return;
+        }
+        self.send_message(&format!(
+            "{:<8}{:<38}{:<10}{}",
+            "PID", "UUID", "STATE", "ALIAS"
+        ));
+        for process in processes {
+            let state = if process.finished {
+                "finished"
+            } else {
+                "running"
+            };
+            self.send_message(&format!(
+                "{:<8}{:<38}{:<10}{}",
+                process.pid,
+                process.id,
+                state,
+                process.alias.as_deref().unwrap_or("-")
+            ));
+        }
+        self.send_prompt();
+    }
+
+    fn join(&mut self, _args: &[&str]) {
+        let results = self.scheduler.join_all();
+        if results.is_empty() {
+            self.send_message("No processes have been spawned");
+            self.send_prompt();
+            return;
+        }
+        for (pid, events) in results {
+            self.send_message(&format!("PID {}:", pid));
+            for event in events {
+                self.send_message(&format!("  {}", event.to_json()));
+            }
+        }
+        self.send_prompt();
+    }
+
     fn start_cluster(&mut self, _args: &[&str]) {
         display::writeout("Starting cluster server!");
         self.vm.bind_cluster_server()
@@ -395,10 +905,18 @@ impl REPL {
             let mut cc =
                 cluster::client::ClusterClient::new(stream).with_alias(self.vm.id.to_string());
             cc.send_hello();
-            if let Some(ref a) = self.vm.alias {
-                if let Ok(mut lock) = self.vm.connection_manager.write() {
-                    lock.add_client(a.to_string(), cc);
-                }
+            match cc.read_hello_ack() {
+                Ok(server_alias) => {
+                    for (alias, ip, port) in cc.peers() {
+                        self.send_message(&format!("Cluster member: {} at {}:{}", alias, ip, port));
+                    }
+                    if let Ok(mut lock) = self.vm.connection_manager.write() {
+                        lock.add_client(server_alias, cc);
+                    }
+                },
+                Err(e) => {
+                    self.send_message(&format!("Cluster did not acknowledge us: {}", e));
+                },
             }
         } else {
             self.send_message("Could not connect to cluster!");
@@ -455,7 +973,7 @@ impl REPL {
 }
 
 mod utils {
-    use std::io::Read;
+    use std::io::{self, Read};
     use std::path::PathBuf;
 
     use std::fs::File;
@@ -486,57 +1004,31 @@ mod utils {
         }
     }
 
+    pub fn write_data_to_path(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    /// Puts a path back together from command arguments. A quoted path may span several
+    /// arguments, which were split at its spaces; an unquoted path is just the first argument.
     pub fn aggreate_path(args: &[&str]) -> Option<PathBuf> {
-        if args.is_empty() {
-            return None;
-        }
-
-        let mut left_single_quote = false;
-        let mut left_double_quote = false;
-
-        let mut path = PathBuf::new();
-
-        for &arg in args {
-            if !left_double_quote && !left_single_quote {
-                if arg.starts_with("\"") && arg.ends_with("\"") {
-                    path.push(&arg.trim_matches(&['\"']));
-                    left_double_quote = false;
-                    break;
-                } else if arg.starts_with("\'") && arg.ends_with("\'") {
-                    path.push(&arg.trim_matches(&['\'']));
-                    left_single_quote = false;
-                    break;
-                } else if arg.starts_with("\"") {
-                    left_double_quote = true;
-                    path.push(&arg[1..]);
-                } else if arg.starts_with("\'") {
-                    left_single_quote = true;
-                    path.push(&arg[1..]);
-                }
-            }
+        let first = args.first()?;
+        let quote = match first.chars().next() {
+            Some(q @ ('"' | '\'')) => q,
+            _ => return Some(PathBuf::from(first)),
+        };
 
-            if left_double_quote {
-                if arg.ends_with("\"") {
-                    path.push(&arg[..arg.len() - 1]);
-                    left_double_quote = false;
-                } else {
-                    path.push(&arg);
-                }
-            } else if left_single_quote {
-                if arg.ends_with("\'") {
-                    path.push(&arg[..arg.len() - 1]);
-                    left_double_quote = true;
-                } else {
-                    path.push(&arg);
-                }
+        let mut parts = vec![];
+        for (i, &arg) in args.iter().enumerate() {
+            let arg = if i == 0 { &arg[1..] } else { arg };
+            if let Some(last) = arg.strip_suffix(quote) {
+                parts.push(last);
+                return Some(PathBuf::from(parts.join(" ")));
             }
+            parts.push(arg);
         }
 
-        if left_double_quote || left_single_quote {
-            return None;
-        }
-
-        Some(path)
+        // The closing quote never came
+        None
     }
 
     pub fn is_valid_path(path: &PathBuf) -> Option<String> {
@@ -639,6 +1131,389 @@ mod tests {
         assert_eq!(expect, repl.vm.program);
     }
 
+    /// 收集 REPL 目前发送出的所有消息
+    fn drain_messages(repl: &REPL) -> String {
+        let mut output = String::new();
+        if let Some(rx) = &repl.rx_pipe {
+            while let Ok(msg) = rx.try_recv() {
+                output.push_str(&msg);
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_find() {
+        let mut repl = REPL::new(VM::new());
+        repl.run_single("load $0 #1");
+        repl.run_single("add $0 $1 $2");
+        repl.run_single("add $2 $2 $3");
+        drain_messages(&repl);
+
+        repl.run_single("!find add");
+        assert!(drain_messages(&repl).contains("Found add at offsets: [4, 8]"));
+        repl.run_single("!find sub");
+        assert!(drain_messages(&repl).contains("sub not found"));
+        repl.run_single("!find bogus");
+        assert!(drain_messages(&repl).contains("Unknown opcode: bogus"));
+    }
+
+    #[test]
+    fn test_export() {
+        let path = std::env::temp_dir().join(format!("lrvm_export_{}.iasm", std::process::id()));
+        let mut repl = REPL::new(VM::new());
+        repl.run_single("load $0 #10");
+        repl.run_single("!registers");
+        repl.run_single("load $1 #5");
+        repl.run_single(&format!("!export {}", path.display()));
+        let exported = read_file_to_string(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exported, ".data\n.code\nload $0 #10\nload $1 #5\n");
+
+        let mut asm = Assembler::new();
+        assert!(asm.assemble(&exported).is_ok());
+
+        repl.run_single("!export /does/not/exist/session.iasm");
+        assert!(drain_messages(&repl).contains("Unable to export session"));
+    }
+
+    #[test]
+    fn test_step() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!step");
+        assert!(drain_messages(&repl).contains("No program loaded"));
+
+        // load $0 #1, load $1 #2, add $0 $1 $2, hlt
+        repl.vm.program = vec![0, 0, 0, 1, 0, 1, 0, 2, 1, 0, 1, 2, 5, 0, 0, 0];
+        for (pc, register) in [(4, 0), (8, 1), (12, 2)] {
+            repl.execute_command("!step");
+            let output = drain_messages(&repl);
+            assert!(output.contains(&format!("pc: {}\n", pc)), "{}", output);
+            assert_eq!(repl.vm.registers[register], register as i32 + 1);
+        }
+        repl.execute_command("!step");
+        assert!(drain_messages(&repl).contains("Program stopped"));
+
+        repl.vm.rewind();
+        repl.vm.registers = [0; 32];
+        repl.execute_command("!continue");
+        assert!(drain_messages(&repl).contains("Program stopped"));
+        assert_eq!(repl.vm.registers[2], 3);
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!watch");
+        assert!(drain_messages(&repl).contains("No registers are watched"));
+        repl.execute_command("!watch $32");
+        assert!(drain_messages(&repl).contains("Invalid register: $32"));
+
+        repl.execute_command("!watch $0");
+        assert!(drain_messages(&repl).contains("Watching $0"));
+        // inc $0 three times, then hlt
+        repl.vm.program = vec![18, 0, 0, 0, 18, 0, 0, 0, 18, 0, 0, 0, 5, 0, 0, 0];
+        for value in 1..=3 {
+            repl.execute_command("!step");
+            let output = drain_messages(&repl);
+            assert!(
+                output.contains(&format!("watch $0 = {} (was {})\n", value, value - 1)),
+                "{}",
+                output
+            );
+        }
+        repl.execute_command("!step");
+        assert!(drain_messages(&repl).contains("watch $0 = 3\n"));
+
+        // Typed instructions run straight away, so they are reported too
+        repl.vm.program.clear();
+        repl.vm.rewind();
+        repl.run_single("inc $0");
+        assert!(drain_messages(&repl).contains("watch $0 = 4 (was 3)"));
+
+        repl.execute_command("!watch remove $0");
+        assert!(drain_messages(&repl).contains("Stopped watching $0"));
+        repl.run_single("inc $0");
+        assert!(!drain_messages(&repl).contains("watch $0"));
+    }
+
+    #[test]
+    fn test_typed_instruction_comment_marker() {
+        let mut repl = REPL::new(VM::new());
+        repl.asm = Assembler::new().with_comment_char("//").unwrap();
+        repl.run_instruction("load $0 #5 // five");
+        assert_eq!(repl.vm.registers[0], 5);
+        // `;` isn't a comment with this marker, parsing stops in front of it after the load
+        repl.run_instruction("load $1 #6 ; six");
+        assert_eq!(repl.vm.registers[1], 6);
+        assert_eq!(repl.vm.program.len(), 8);
+    }
+
+    #[test]
+    fn test_typed_immediate_out_of_range() {
+        let mut repl = REPL::new(VM::new());
+        // An offset that doesn't fit in LOADMO's byte is refused rather than wrapped
+        repl.run_instruction("loadmo $1 $0 #200");
+        assert!(drain_messages(&repl).contains("Immediate 200 doesn't fit"));
+        assert!(repl.vm.program.is_empty());
+    }
+
+    #[test]
+    fn test_watch_typed_instruction() {
+        // The local loop in run() goes through run_instruction
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!watch $1");
+        drain_messages(&repl);
+        repl.run_instruction("load $1 #7");
+        assert!(drain_messages(&repl).contains("watch $1 = 7 (was 0)"));
+        repl.run_instruction("load $2 #9");
+        assert!(drain_messages(&repl).contains("watch $1 = 7\n"));
+
+        // A line that doesn't parse runs nothing, so nothing is reported
+        repl.run_instruction("bogus $1");
+        let output = drain_messages(&repl);
+        assert!(output.contains("Unable to parse input"), "{}", output);
+        assert!(!output.contains("watch $1"), "{}", output);
+    }
+
+    #[test]
+    fn test_help() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!help");
+        let output = drain_messages(&repl);
+        for command in [
+            "!help",
+            "!quit",
+            "!history",
+            "!program",
+            "!clear",
+            "!reset",
+            "!step",
+            "!continue",
+            "!breakpoint",
+            "!watch",
+            "!registers",
+            "!float_registers",
+            "!symbols",
+            "!start_cluster",
+            "!join_cluster",
+            "!cluster_members",
+            "!time",
+            "!opcodes",
+            "!export",
+            "!save",
+            "!find",
+            "!header",
+            "!disassemble",
+            "!load_file",
+            "!spawn",
+            "!ps",
+            "!join",
+        ] {
+            assert!(
+                output.contains(command),
+                "{} missing from:\n{}",
+                command,
+                output
+            );
+        }
+
+        repl.execute_command("!bogus");
+        assert!(drain_messages(&repl).contains("Type !help"));
+    }
+
+    #[test]
+    fn test_float_registers() {
+        let mut repl = REPL::new(VM::new());
+        repl.run_single("loadf64 $0 #12");
+        repl.vm.float_registers[3] = 2.5;
+        drain_messages(&repl);
+
+        repl.execute_command("!float_registers");
+        let output = drain_messages(&repl);
+        assert!(output.contains("Listing float registers"), "{}", output);
+        assert!(output.contains("12.0,"), "{}", output);
+        assert!(output.contains("2.5,"), "{}", output);
+
+        repl.execute_command("!registers float");
+        assert_eq!(drain_messages(&repl), output);
+    }
+
+    #[test]
+    fn test_history_navigation() {
+        let mut history = CommandManager::new();
+        assert_eq!(history.last_command(), None);
+        assert_eq!(history.next_command(), None);
+
+        history.push("load $0 #1".to_string());
+        history.push("!registers".to_string());
+        assert_eq!(history.last_command(), Some("!registers".to_string()));
+        assert_eq!(history.last_command(), Some("load $0 #1".to_string()));
+        // The oldest entry is as far back as it goes
+        assert_eq!(history.last_command(), Some("load $0 #1".to_string()));
+
+        assert_eq!(history.next_command(), Some("!registers".to_string()));
+        // Past the newest entry there is nothing to recall, however often down is pressed
+        assert_eq!(history.next_command(), None);
+        assert_eq!(history.next_command(), None);
+        assert_eq!(history.last_command(), Some("!registers".to_string()));
+
+        history.push("hlt".to_string());
+        assert_eq!(history.currnet_command(), None);
+        assert_eq!(history.last_command(), Some("hlt".to_string()));
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut repl = REPL::new(VM::new());
+        // load $0 #1, load $1 #2, add $0 $1 $2, hlt
+        repl.vm.program = vec![0, 0, 0, 1, 0, 1, 0, 2, 1, 0, 1, 2, 5, 0, 0, 0];
+        repl.execute_command("!breakpoint 8");
+        assert!(drain_messages(&repl).contains("Breakpoint set at 8"));
+
+        repl.execute_command("!continue");
+        let output = drain_messages(&repl);
+        assert!(output.contains("Paused at breakpoint 8"), "{}", output);
+        assert_eq!(repl.vm.pc(), 8);
+        assert_eq!(repl.vm.registers[0..3], [1, 2, 0]);
+
+        repl.execute_command("!continue");
+        assert!(drain_messages(&repl).contains("Program stopped"));
+        assert_eq!(repl.vm.registers[2], 3);
+
+        // Stepping onto a breakpoint runs that instruction instead of reporting a stop
+        repl.vm.rewind();
+        repl.vm.registers = [0; 32];
+        for (pc, register) in [(4, 0), (8, 1), (12, 2)] {
+            repl.execute_command("!step");
+            let output = drain_messages(&repl);
+            assert!(output.contains(&format!("pc: {}\n", pc)), "{}", output);
+            assert!(!output.contains("Program stopped"), "{}", output);
+            assert_eq!(repl.vm.registers[register], register as i32 + 1);
+        }
+
+        repl.execute_command("!breakpoint remove 8");
+        assert!(drain_messages(&repl).contains("Breakpoint at 8 removed"));
+        repl.execute_command("!breakpoint nowhere");
+        assert!(drain_messages(&repl).contains("Invalid breakpoint offset"));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut repl = REPL::new(VM::new());
+        repl.run_single("load $0 #10");
+        repl.run_single("loadf64 $1 #3");
+        repl.execute_command("!spawn docs/examples/hlt.iasm");
+        assert!(!repl.vm.program.is_empty());
+        assert_eq!(repl.vm.registers[0], 10);
+        drain_messages(&repl);
+
+        repl.execute_command("!reset");
+        assert!(drain_messages(&repl).contains("VM and assembler reset"));
+        assert!(repl.vm.program.is_empty());
+        assert_eq!(repl.vm.registers, [0; 32]);
+        assert_eq!(repl.vm.float_registers, [0.0; 32]);
+        assert_eq!(repl.vm.instruction_count(), 0);
+        assert!(repl.asm.symbols.symbols.is_empty());
+    }
+
+    #[test]
+    fn test_save() {
+        let path = std::env::temp_dir().join(format!("lrvm_save {}.lrvm", std::process::id()));
+        let mut repl = REPL::new(VM::new());
+        let mut asm = Assembler::new();
+        repl.vm.program = asm
+            .assemble(".data\nhi: .asciiz 'hi'\n.code\nload $0 #10\nhlt\n")
+            .unwrap();
+        repl.execute_command(&format!("!save \"{}\"", path.display()));
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved, repl.vm.program);
+        assert!(drain_messages(&repl).contains("Saved 79 bytes"));
+
+        repl.execute_command("!save /does/not/exist/program.lrvm");
+        assert!(drain_messages(&repl).contains("Unable to save program"));
+    }
+
+    #[test]
+    fn test_opcodes() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!opcodes");
+        let output = drain_messages(&repl);
+        let (implemented, unimplemented) = output.split_once("Unimplemented opcodes:").unwrap();
+        assert!(implemented.contains("LOAD"));
+        assert!(unimplemented.contains("CLOOP"));
+    }
+
+    #[test]
+    fn test_header() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!header");
+        assert!(drain_messages(&repl).contains("too short to have a header"));
+
+        let mut asm = Assembler::new();
+        repl.vm.program = asm
+            .assemble(".data\nhi: .asciiz 'hi'\n.code\nhlt\n")
+            .unwrap();
+        repl.execute_command("!header");
+        let output = drain_messages(&repl);
+        assert!(
+            output.contains("Magic: [45, 50, 49, 45] (valid)"),
+            "{}",
+            output
+        );
+        assert!(output.contains("RO section length: 3"), "{}", output);
+        assert!(output.contains("Required VM version: 0"), "{}", output);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let test_file = get_absolute_path("docs/examples/counting_loop.iasm");
+        let contents = read_file_to_string(test_file.to_str().unwrap()).ok();
+        let mut repl = REPL::new(VM::new());
+        repl.load_file(&[""], &contents);
+        drain_messages(&repl);
+
+        repl.execute_command("!disassemble");
+        let output = drain_messages(&repl);
+        for line in ["0: load $0 #100", "12: inc $2", "16: neq $0 $2", "24: hlt"] {
+            assert!(output.contains(line), "{}", output);
+        }
+
+        let mut asm = Assembler::new();
+        repl.vm.program = asm
+            .assemble(".data\nhi: .asciiz 'hi'\n.code\nload $1 #7\n")
+            .unwrap();
+        repl.vm.program.extend_from_slice(&[250, 0, 0, 0]);
+        repl.execute_command("!disassemble");
+        let output = drain_messages(&repl);
+        assert!(
+            output.contains("Header and read-only data: 71 bytes"),
+            "{}",
+            output
+        );
+        assert!(output.contains("71: load $1 #7"), "{}", output);
+        assert!(
+            output.contains("75: igl ; illegal opcode 250"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!time");
+        assert!(drain_messages(&repl).contains("No program loaded"));
+
+        // load $0 #1, load $1 #2, hlt
+        repl.vm.program = vec![0, 0, 0, 1, 0, 1, 0, 2, 5, 0, 0, 0];
+        repl.execute_command("!time");
+        let output = drain_messages(&repl);
+        assert!(output.contains("Executed 3 instructions in"), "{}", output);
+        assert_eq!(repl.vm.registers[1], 2);
+    }
+
     #[test]
     fn test_spawn() {
         let test_file = get_absolute_path("docs/examples/hlt.iasm");
@@ -655,9 +1530,53 @@ mod tests {
         let expect = vec![
             45, 50, 49, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0,
         ];
 
         assert_eq!(expect, repl.vm.program);
     }
+
+    #[test]
+    fn test_ps() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!ps");
+        let output = drain_messages(&repl);
+        assert!(output.contains("No processes have been spawned"));
+        assert!(output.ends_with(PROMPT), "{}", output);
+
+        repl.execute_command("!spawn docs/examples/hlt.iasm");
+        repl.execute_command("!spawn docs/examples/hlt.iasm");
+        drain_messages(&repl);
+
+        repl.execute_command("!ps");
+        let output = drain_messages(&repl);
+        let table = output
+            .strip_suffix(PROMPT)
+            .expect("no prompt after the table");
+        let pids: Vec<&str> = table
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().next())
+            .collect();
+        assert_eq!(pids, vec!["0", "1"], "{}", output);
+        assert!(output.contains(&repl.vm.id.to_string()), "{}", output);
+    }
+
+    #[test]
+    fn test_join() {
+        let mut repl = REPL::new(VM::new());
+        repl.execute_command("!spawn docs/examples/hlt.iasm");
+        drain_messages(&repl);
+
+        repl.execute_command("!join");
+        let output = drain_messages(&repl);
+        assert!(output.contains("PID 0:"), "{}", output);
+        assert!(output.contains("GracefulStop"), "{}", output);
+        assert!(output.ends_with(PROMPT), "{}", output);
+
+        repl.execute_command("!join");
+        let output = drain_messages(&repl);
+        assert!(output.contains("No processes have been spawned"));
+        assert!(output.ends_with(PROMPT), "{}", output);
+    }
 }
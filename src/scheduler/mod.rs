@@ -1,4 +1,6 @@
-use std::thread;
+use std::{collections::HashMap, thread};
+
+use uuid::Uuid;
 
 use crate::{
     util::display,
@@ -7,9 +9,24 @@ use crate::{
 
 const MAX_PID: u32 = 50000;
 
+/// 进程表中的一项, 供 `!ps` 之类的命令展示
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub id: Uuid,
+    pub alias: Option<String>,
+    pub finished: bool,
+}
+
+struct Process {
+    info: ProcessInfo,
+    handle: thread::JoinHandle<Vec<VMEvent>>,
+}
+
 pub struct Scheduler {
     max_pid: u32,
     next_pid: u32,
+    processes: HashMap<u32, Process>,
 }
 
 impl Scheduler {
@@ -17,11 +34,21 @@ impl Scheduler {
         Scheduler {
             next_pid: 0,
             max_pid: MAX_PID,
+            processes: HashMap::new(),
         }
     }
 
-    pub fn get_thread(&self, mut vm: VM) -> thread::JoinHandle<Vec<VMEvent>> {
-        thread::spawn(move || {
+    /// Runs the VM on a new thread and returns the PID assigned to it.
+    /// Returns `None` without running the VM when every PID below max_pid is still taken.
+    pub fn get_thread(&mut self, mut vm: VM) -> Option<u32> {
+        let pid = self._next_pid()?;
+        let info = ProcessInfo {
+            pid,
+            id: vm.id,
+            alias: vm.alias.clone(),
+            finished: false,
+        };
+        let handle = thread::spawn(move || {
             let events = vm.run();
             display::writeout("VM Events");
             display::writeout("--------------------------");
@@ -29,7 +56,43 @@ impl Scheduler {
                 println!("{:#?}", event);
             }
             events
-        })
+        });
+        self.processes.insert(pid, Process { info, handle });
+        Some(pid)
+    }
+
+    /// PIDs of every VM spawned by this scheduler, in ascending order
+    pub fn processes(&self) -> Vec<u32> {
+        let mut pids: Vec<u32> = self.processes.keys().copied().collect();
+        pids.sort_unstable();
+        pids
+    }
+
+    /// Waits for every spawned VM to finish and hands back the events each one produced,
+    /// ordered by PID. The process table is empty afterwards.
+    /// 线程 panic 的 VM 没有事件可返回, 对应的列表为空
+    pub fn join_all(&mut self) -> Vec<(u32, Vec<VMEvent>)> {
+        let mut results: Vec<(u32, Vec<VMEvent>)> = self
+            .processes
+            .drain()
+            .map(|(pid, process)| (pid, process.handle.join().unwrap_or_default()))
+            .collect();
+        results.sort_unstable_by_key(|(pid, _)| *pid);
+        results
+    }
+
+    /// PID, UUID and alias of every spawned VM, ordered by PID
+    pub fn process_infos(&self) -> Vec<ProcessInfo> {
+        let mut infos: Vec<ProcessInfo> = self
+            .processes
+            .values()
+            .map(|process| ProcessInfo {
+                finished: process.handle.is_finished(),
+                ..process.info.clone()
+            })
+            .collect();
+        infos.sort_unstable_by_key(|info| info.pid);
+        infos
     }
 
     pub fn get_next_pid(&self) -> u32 {
@@ -39,21 +102,107 @@ impl Scheduler {
         self.max_pid
     }
 
-    fn _next_pid(&mut self) -> u32 {
-        let result = self.next_pid;
+    /// 到达 max_pid 后从 0 重新开始, 跳过仍在进程表中的 PID.
+    /// 绕一整圈都没有空闲 PID 时返回 None
+    fn _next_pid(&mut self) -> Option<u32> {
+        for _ in 0..self.max_pid {
+            let candidate = self.next_pid;
+            self.advance_pid();
+            if !self.processes.contains_key(&candidate) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    fn advance_pid(&mut self) {
         self.next_pid += 1;
-        result
+        if self.next_pid >= self.max_pid {
+            self.next_pid = 0;
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    #[allow(unused_imports)]
-    use crate::scheduler::Scheduler;
+    use crate::{assembler::Assembler, scheduler::Scheduler, vm::VM};
 
     #[test]
     fn test_make_scheduler() {
         let s = Scheduler::new();
         assert_eq!(s.next_pid, 0);
     }
+
+    #[test]
+    fn test_sequential_pids() {
+        let mut s = Scheduler::new();
+        assert_eq!(s.get_thread(VM::new()), Some(0));
+        assert_eq!(s.get_thread(VM::new()), Some(1));
+        assert_eq!(s.get_thread(VM::new()), Some(2));
+        assert_eq!(s.get_next_pid(), 3);
+    }
+
+    #[test]
+    fn test_process_table_grows() {
+        let mut s = Scheduler::new();
+        assert!(s.processes().is_empty());
+        s.get_thread(VM::new());
+        assert_eq!(s.processes(), vec![0]);
+        s.get_thread(VM::new());
+        assert_eq!(s.processes(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_join_all() {
+        let mut s = Scheduler::new();
+        let mut vm = VM::new();
+        vm.program = Assembler::new().assemble(".data\n.code\nhlt\n").unwrap();
+        let pid = s.get_thread(vm).unwrap();
+
+        let results = s.join_all();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, pid);
+        assert!(results[0]
+            .1
+            .iter()
+            .any(|event| event.to_json().contains("\"event\":\"GracefulStop\"")));
+        assert!(s.processes().is_empty());
+    }
+
+    #[test]
+    fn test_process_infos() {
+        let mut s = Scheduler::new();
+        let vm = VM::new().with_alias("worker".to_string());
+        let id = vm.id;
+        s.get_thread(vm);
+        let infos = s.process_infos();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].pid, 0);
+        assert_eq!(infos[0].id, id);
+        assert_eq!(infos[0].alias, Some("worker".to_string()));
+    }
+
+    #[test]
+    fn test_pid_wraps_at_max() {
+        let mut s = Scheduler::new();
+        s.max_pid = 2;
+        assert_eq!(s.get_thread(VM::new()), Some(0));
+        assert_eq!(s.get_thread(VM::new()), Some(1));
+        assert_eq!(s.get_next_pid(), 0);
+    }
+
+    #[test]
+    fn test_full_process_table() {
+        let mut s = Scheduler::new();
+        s.max_pid = 3;
+        for pid in 0..3 {
+            assert_eq!(s.get_thread(VM::new()), Some(pid));
+        }
+        assert_eq!(s.get_thread(VM::new()), None);
+        assert_eq!(s.processes(), vec![0, 1, 2]);
+
+        // Joining frees the PIDs again
+        s.join_all();
+        assert_eq!(s.get_thread(VM::new()), Some(0));
+    }
 }
@@ -0,0 +1,30 @@
+use std::{
+    io,
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// How long to wait between two checks of the shutdown flag while no one is connecting
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Like `TcpListener::incoming`, but ends once `shutdown` is set.
+/// The listener is switched to non-blocking so the flag can be checked between accepts,
+/// the streams handed out are blocking again.
+pub fn incoming_until<'a>(
+    listener: &'a TcpListener,
+    shutdown: &'a AtomicBool,
+) -> io::Result<impl Iterator<Item = io::Result<TcpStream>> + 'a> {
+    listener.set_nonblocking(true)?;
+    Ok(std::iter::from_fn(move || loop {
+        if shutdown.load(Ordering::SeqCst) {
+            return None;
+        }
+        match listener.accept() {
+            Ok((stream, _)) => return Some(stream.set_nonblocking(false).map(|_| stream)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+            Err(e) => return Some(Err(e)),
+        }
+    }))
+}
@@ -1,18 +1,26 @@
 use std::{
+    cmp,
+    collections::{HashMap, HashSet},
     f64::EPSILON,
-    io::Cursor,
+    fmt, fs,
+    io::{self, Cursor},
     net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
     thread,
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt};
 use chrono::{DateTime, Utc};
 use log::{debug, error, info};
 use uuid::Uuid;
 
 use crate::{
-    assembler::PIE_HEADER_PREFIX,
+    assembler::{
+        PIE_HEADER_LENGTH, PIE_HEADER_PREFIX, PIE_HEADER_RO_LENGTH_OFFSET,
+        PIE_HEADER_VERSION_OFFSET,
+    },
     cluster::{self, manager::Manager},
     instruction::Opcode,
     util::display,
@@ -20,6 +28,196 @@ use crate::{
 
 pub const DEFAULT_HEAP_STARTING_SIZE: usize = 64;
 
+/// Version of this VM, checked against the version a program declares with `.require_version`
+pub const VM_VERSION: u32 = 1;
+
+/// The most bytes the stack may hold before a push crashes the VM
+pub const MAX_STACK_SIZE: usize = 64 * 1024;
+
+/// Opcodes that `execute_instruction` knows how to run. Anything else is refused before dispatch,
+/// so an opcode only works once it is listed here as well as given a match arm.
+const IMPLEMENTED_OPCODES: &[Opcode] = &[
+    Opcode::LOAD,
+    Opcode::ADD,
+    Opcode::SUB,
+    Opcode::MUL,
+    Opcode::DIV,
+    Opcode::INC,
+    Opcode::DEC,
+    Opcode::HLT,
+    Opcode::IGL,
+    Opcode::JMP,
+    Opcode::JMPF,
+    Opcode::JMPB,
+    Opcode::EQ,
+    Opcode::NEQ,
+    Opcode::GTE,
+    Opcode::LTE,
+    Opcode::LT,
+    Opcode::GT,
+    Opcode::JMPE,
+    Opcode::ALOC,
+    Opcode::PRTS,
+    Opcode::LOADF64,
+    Opcode::ADDF64,
+    Opcode::SUBF64,
+    Opcode::MULF64,
+    Opcode::DIVF64,
+    Opcode::EQF64,
+    Opcode::NEQF64,
+    Opcode::GTF64,
+    Opcode::GTEF64,
+    Opcode::LTF64,
+    Opcode::LTEF64,
+    Opcode::SHL,
+    Opcode::SHR,
+    Opcode::SYSCALL,
+    Opcode::FILL,
+    Opcode::ROL,
+    Opcode::ROR,
+    Opcode::CLZ,
+    Opcode::POPCNT,
+    Opcode::LOADMO,
+    Opcode::LOAD32,
+    Opcode::PUSHA,
+    Opcode::POPA,
+    Opcode::RAND,
+    Opcode::TIME,
+    Opcode::READI,
+    Opcode::CLAMP,
+    Opcode::MIN,
+    Opcode::MAX,
+    Opcode::MINF64,
+    Opcode::MAXF64,
+    Opcode::CLAMPF64,
+    Opcode::ADDI,
+    Opcode::TAS,
+    Opcode::AND,
+    Opcode::OR,
+    Opcode::XOR,
+    Opcode::NOT,
+    Opcode::COPYRO,
+    Opcode::CALL,
+    Opcode::RET,
+    Opcode::HEAPSZ,
+    Opcode::LUI,
+    Opcode::MOD,
+    Opcode::MOV,
+    Opcode::PRTI,
+];
+
+/// Crash code for a program whose header is missing or malformed
+pub const CRASH_BAD_HEADER: u32 = 1;
+/// Crash code for a `SYSCALL` whose number has no registered handler
+pub const CRASH_UNKNOWN_SYSCALL: u32 = 2;
+/// Crash code for a memory access that falls outside the heap
+pub const CRASH_HEAP_OUT_OF_BOUNDS: u32 = 3;
+/// Crash code for a push that would grow the stack past `MAX_STACK_SIZE`
+pub const CRASH_STACK_OVERFLOW: u32 = 4;
+/// Crash code for a pop from a stack that doesn't hold enough bytes
+pub const CRASH_STACK_UNDERFLOW: u32 = 5;
+/// Crash code for an instruction that can't give a reproducible result in deterministic mode
+pub const CRASH_NONDETERMINISTIC: u32 = 6;
+/// Crash code for a program that needs a newer VM than this one
+pub const CRASH_UNSUPPORTED_VERSION: u32 = 7;
+/// Crash code for a range operand whose lower bound is above its upper bound
+pub const CRASH_INVALID_RANGE: u32 = 8;
+/// Crash code for `run` being called before any program was loaded
+pub const CRASH_NO_PROGRAM: u32 = 9;
+/// Crash code for a read that falls outside the read-only section
+pub const CRASH_RO_OUT_OF_BOUNDS: u32 = 10;
+/// Crash code for a jump whose target lies outside the code section
+pub const CRASH_JUMP_OUT_OF_BOUNDS: u32 = 11;
+/// Crash code for an integer division or remainder with a zero divisor
+pub const CRASH_DIVIDE_BY_ZERO: u32 = 12;
+/// Crash code for integer arithmetic whose result doesn't fit in 32 bits
+pub const CRASH_ARITHMETIC_OVERFLOW: u32 = 13;
+
+/// Where the `RAND` sequence starts in deterministic mode
+const DETERMINISTIC_RAND_SEED: u32 = 0x2545_F491;
+
+/// A check that a register holds a value once the program has run, declared with `.assert_reg`
+#[derive(Debug, PartialEq, Clone)]
+pub struct RegisterAssertion {
+    pub register: u8,
+    pub expected: i32,
+}
+
+/// A host function that programs can invoke with `SYSCALL #n`
+pub type SyscallHandler = Arc<dyn Fn(&mut VM) + Send + Sync>;
+
+/// Host functions registered on a VM, keyed by syscall number
+#[derive(Clone, Default)]
+pub struct SyscallTable {
+    handlers: HashMap<u16, SyscallHandler>,
+}
+
+impl fmt::Debug for SyscallTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.handlers.keys()).finish()
+    }
+}
+
+/// Custom behaviour for an opcode, registered with `VM::register_handler`
+pub trait OpcodeHandler: Send + Sync {
+    /// Runs the instruction. The pc points just past the opcode byte, so the handler reads its own
+    /// operands with `next_8_bits`/`next_16_bits` and must leave the pc at the next instruction.
+    fn execute(&self, vm: &mut VM);
+}
+
+impl<F> OpcodeHandler for F
+where
+    F: Fn(&mut VM) + Send + Sync,
+{
+    fn execute(&self, vm: &mut VM) {
+        self(vm)
+    }
+}
+
+#[derive(Clone)]
+enum Handler {
+    /// One of the arms of `execute_builtin`
+    Builtin(Opcode),
+    Custom(Arc<dyn OpcodeHandler>),
+}
+
+/// What runs for each opcode byte. Every implemented opcode starts out mapped to its built-in
+/// behaviour, and so does every byte that isn't an opcode at all, which decodes as `IGL`.
+/// Opcodes with no entry are reported as unimplemented and skipped.
+#[derive(Clone)]
+struct HandlerTable {
+    entries: Vec<Option<Handler>>,
+}
+
+impl Default for HandlerTable {
+    fn default() -> Self {
+        let entries = (0..=u8::MAX)
+            .map(Opcode::from)
+            .map(|opcode| {
+                if IMPLEMENTED_OPCODES.contains(&opcode) {
+                    Some(Handler::Builtin(opcode))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        HandlerTable { entries }
+    }
+}
+
+impl fmt::Debug for HandlerTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only the custom handlers are interesting, the built-ins are always the same
+        let custom = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| matches!(entry, Some(Handler::Custom(_))))
+            .map(|(byte, _)| byte);
+        f.debug_set().entries(custom).finish()
+    }
+}
+
 pub fn get_test_vm() -> VM {
     let mut test_vm = VM::new();
     test_vm.equal_flag = false;
@@ -33,8 +231,16 @@ pub fn get_test_vm() -> VM {
 #[derive(Debug, Clone)]
 enum VMEventType {
     Start,
-    GracefulStop { code: u32 },
-    Crash { code: u32 },
+    GracefulStop {
+        code: u32,
+    },
+    Crash {
+        code: u32,
+    },
+    /// The program was stopped after running the most instructions it was allowed
+    InstructionLimitReached,
+    /// The pc reached a breakpoint, the program can carry on from there
+    BreakpointHit,
 }
 
 #[derive(Debug, Clone)]
@@ -44,6 +250,153 @@ pub struct VMEvent {
     application_id: Uuid,
 }
 
+impl VMEvent {
+    /// The event as a JSON object, with the time in RFC 3339 format, for tools that read the
+    /// output of a run
+    pub fn to_json(&self) -> String {
+        let (name, code) = match &self.event {
+            VMEventType::Start => ("Start", None),
+            VMEventType::GracefulStop { code } => ("GracefulStop", Some(*code)),
+            VMEventType::Crash { code } => ("Crash", Some(*code)),
+            VMEventType::InstructionLimitReached => ("InstructionLimitReached", None),
+            VMEventType::BreakpointHit => ("BreakpointHit", None),
+        };
+        let code = match code {
+            Some(code) => format!(",\"code\":{}", code),
+            None => String::new(),
+        };
+        format!(
+            "{{\"event\":\"{}\"{},\"at\":\"{}\",\"application_id\":\"{}\"}}",
+            name,
+            code,
+            self.at.to_rfc3339(),
+            self.application_id
+        )
+    }
+}
+
+/// Writes a list of events, e.g. the ones `VM::run` returns, as a JSON array
+pub fn events_to_json(events: &[VMEvent]) -> String {
+    let events: Vec<String> = events.iter().map(VMEvent::to_json).collect();
+    format!("[{}]", events.join(","))
+}
+
+/// The execution state of a VM at one moment, taken with `VM::snapshot` and put back with
+/// `VM::restore`. It leaves out what the VM is rather than what it is doing, such as its id,
+/// connections and settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub registers: [i32; 32],
+    pub float_registers: [f64; 32],
+    pub pc: usize,
+    pub heap: Vec<u8>,
+    pub stack: Vec<u8>,
+    pub equal_flag: bool,
+    pub loop_counter: usize,
+    pub reminder: usize,
+    pub ro_data: Vec<u8>,
+}
+
+/// How a program run by `VM::run_summary` came to an end
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunOutcome {
+    /// The program stopped on its own, e.g. with `HLT`
+    Halted,
+    /// The VM stopped the program because something went wrong
+    Crashed,
+    /// The program used up its instruction budget before finishing
+    LimitReached,
+    /// The program is paused at a breakpoint and can be resumed
+    Paused,
+}
+
+/// Why a program's header can't be run, found by `VM::from_bytecode` before running or by
+/// `run` as it starts
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    /// There are no bytes at all
+    Empty,
+    /// The bytes don't start with the magic prefix, e.g. a program assembled without a header
+    MissingPrefix,
+    /// The bytes stop before the end of the header and the 4 byte slot after it
+    Truncated { len: usize },
+    /// The header asks for a newer VM than this one
+    UnsupportedVersion { required: u32 },
+    /// The header's read-only section length reaches past the end of the program
+    RoSectionOutOfBounds { ro_length: usize },
+}
+
+impl HeaderError {
+    /// The `CRASH_*` code `run` reports for this problem
+    pub fn crash_code(&self) -> u32 {
+        match self {
+            HeaderError::Empty => CRASH_NO_PROGRAM,
+            HeaderError::UnsupportedVersion { .. } => CRASH_UNSUPPORTED_VERSION,
+            HeaderError::MissingPrefix
+            | HeaderError::Truncated { .. }
+            | HeaderError::RoSectionOutOfBounds { .. } => CRASH_BAD_HEADER,
+        }
+    }
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::Empty => write!(f, "No program loaded"),
+            HeaderError::MissingPrefix => {
+                write!(
+                    f,
+                    "Header was incorrect: the program doesn't start with the magic prefix"
+                )
+            },
+            HeaderError::Truncated { len } => write!(
+                f,
+                "Header was incorrect: the program is {} bytes, the header needs {}",
+                len,
+                PIE_HEADER_LENGTH + 4
+            ),
+            HeaderError::UnsupportedVersion { required } => write!(
+                f,
+                "Program requires VM version {}, but this is version {}",
+                required, VM_VERSION
+            ),
+            HeaderError::RoSectionOutOfBounds { ro_length } => write!(
+                f,
+                "The read-only section of {} bytes runs past the end of the program",
+                ro_length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/// Runs assembled bytecode on a new VM, checking its header first.
+/// Shorthand for `VM::from_bytecode(bytes)?.run_summary()`.
+pub fn run_program(bytes: Vec<u8>) -> Result<RunResult, HeaderError> {
+    Ok(VM::from_bytecode(bytes)?.run_summary())
+}
+
+/// What `VM::run_summary` reports about a finished program
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    pub outcome: RunOutcome,
+    /// The exit code of a halted program, or the `CRASH_*` code of a crashed one.
+    /// A program stopped by the instruction limit reports 0.
+    pub code: u32,
+    /// Where the pc was left, which for a crash is the instruction that faulted
+    pub pc: usize,
+    /// Every event recorded by the VM, the same list `run` returns
+    pub events: Vec<VMEvent>,
+}
+
+impl RunResult {
+    /// True if the program halted with exit code 0
+    pub fn is_success(&self) -> bool {
+        self.outcome == RunOutcome::Halted && self.code == 0
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VM {
     // Simulate hard registers
@@ -72,6 +425,8 @@ pub struct VM {
     loop_counter: usize,
     /// Contains the read-only section data
     ro_data: Vec<u8>,
+    /// 只读段中每个字符串的结束位置 (终止符的下标), 以起始位置为键, 由 `set_ro_data` 建立
+    ro_string_ends: HashMap<usize, usize>,
     /// 用于标识这个虚拟机的唯一随机生成的 UUID
     pub id: Uuid,
     /// Keeps a list of events for a particular VM
@@ -80,6 +435,33 @@ pub struct VM {
     server_addr: Option<String>,
     /// Port the server will bind to for server-to-server communications
     pub server_port: Option<String>,
+    /// Host functions programs can call into with `SYSCALL`
+    syscalls: SyscallTable,
+    /// What each opcode byte does when executed
+    handlers: HandlerTable,
+    /// Where to write a core dump when a program crashes, if anywhere
+    core_dump_path: Option<PathBuf>,
+    /// Register checks to verify once `run` finishes
+    assertions: Vec<RegisterAssertion>,
+    /// Number of instructions executed since the program was last started
+    instruction_count: u64,
+    /// Whether debug builds check that every instruction starts on a 4 byte boundary and
+    /// consumes exactly its own width, to catch the encoder and decoder disagreeing
+    check_instruction_width: bool,
+    /// When set, instructions that depend on the outside world give fixed results or crash,
+    /// so every VM running the same program ends up in the same state
+    deterministic: bool,
+    /// State of the `RAND` generator used in deterministic mode
+    rand_state: u32,
+    /// Whether integer arithmetic wraps on overflow instead of crashing
+    wrapping_arithmetic: bool,
+    /// The most instructions a run may execute before it is stopped, if there is a limit
+    max_instructions: Option<u64>,
+    /// Offsets of instructions to pause at before executing them
+    breakpoints: HashSet<usize>,
+    /// The breakpoint the VM last paused at, so resuming executes that instruction instead of
+    /// pausing on it again
+    paused_at: Option<usize>,
 }
 
 impl VM {
@@ -89,6 +471,7 @@ impl VM {
             float_registers: [0.0; 32],
             program: vec![],
             ro_data: vec![],
+            ro_string_ends: HashMap::new(),
             heap: vec![0; DEFAULT_HEAP_STARTING_SIZE],
             stack: vec![],
             connection_manager: Arc::new(RwLock::new(Manager::new())),
@@ -102,9 +485,30 @@ impl VM {
             logical_cores: num_cpus::get(),
             server_addr: None,
             server_port: None,
+            syscalls: SyscallTable::default(),
+            handlers: HandlerTable::default(),
+            core_dump_path: None,
+            assertions: vec![],
+            instruction_count: 0,
+            check_instruction_width: true,
+            deterministic: false,
+            rand_state: DETERMINISTIC_RAND_SEED,
+            wrapping_arithmetic: false,
+            max_instructions: None,
+            breakpoints: HashSet::new(),
+            paused_at: None,
         }
     }
 
+    /// A new VM with `bytes` loaded as its program. The header is checked here, so a bad one is
+    /// an error straight away rather than a crash once the program runs.
+    pub fn from_bytecode(bytes: Vec<u8>) -> Result<VM, HeaderError> {
+        let mut vm = VM::new();
+        vm.program = bytes;
+        vm.check_header()?;
+        Ok(vm)
+    }
+
     pub fn run(&mut self) -> Vec<VMEvent> {
         self.events.push(VMEvent {
             event: VMEventType::Start,
@@ -112,39 +516,395 @@ impl VM {
             application_id: self.id.clone(),
         });
 
-        if !self.verify_header() {
+        if let Err(e) = self.check_header() {
             self.events.push(VMEvent {
-                event: VMEventType::Crash { code: 1 },
+                event: VMEventType::Crash {
+                    code: e.crash_code(),
+                },
                 at: Utc::now(),
-                application_id: self.id.clone(),
+                application_id: self.id,
             });
-            display::writeout("Header was incorrect");
+            display::e_writeout(&e.to_string());
             return self.events.clone();
         }
+        // The read-only section sits between the code offset and the code, copy it out so
+        // PRTS and COPYRO can read it. `check_header` made sure it is all there.
+        let ro_start = PIE_HEADER_LENGTH + 4;
+        let ro = self.program[ro_start..ro_start + self.ro_length()].to_vec();
+        self.set_ro_data(ro);
         // If the header is valid, we need to change the PC to be at bit 65.
-        self.pc = 64 + 4 + self.get_starting_offset();
+        self.rewind();
 
         let mut is_done = None;
+        let mut instruction_pc = self.pc;
         while is_done.is_none() {
+            instruction_pc = self.pc;
             is_done = self.execute_instruction();
         }
 
+        let event = is_done.unwrap();
+        let crashed = matches!(event, VMEventType::Crash { .. });
+        if crashed {
+            // Leave the pc pointing at the instruction that faulted rather than past it
+            self.pc = instruction_pc;
+        }
         self.events.push(VMEvent {
-            event: VMEventType::GracefulStop {
-                code: is_done.unwrap(),
-            },
+            event,
             at: Utc::now(),
             application_id: self.id.clone(),
         });
+
+        if crashed {
+            if let Some(path) = &self.core_dump_path {
+                if let Err(e) = self.dump_core(path) {
+                    display::e_writeout(&format!("Unable to write core dump: {}", e));
+                }
+            }
+        }
+        if !self.assertions.is_empty()
+            && !matches!(
+                self.events.last().map(|e| &e.event),
+                Some(VMEventType::BreakpointHit)
+            )
+        {
+            self.report_assertions();
+        }
         self.events.clone()
     }
 
+    /// Runs the program like `run`, but sums up how it ended instead of handing back raw events
+    pub fn run_summary(&mut self) -> RunResult {
+        let events = self.run();
+        let (outcome, code) = match events.last().map(|e| &e.event) {
+            Some(VMEventType::Crash { code }) => (RunOutcome::Crashed, *code),
+            Some(VMEventType::GracefulStop { code }) => (RunOutcome::Halted, *code),
+            Some(VMEventType::InstructionLimitReached) => (RunOutcome::LimitReached, 0),
+            Some(VMEventType::BreakpointHit) => (RunOutcome::Paused, 0),
+            // `run` always ends with a stop, a crash or the instruction limit
+            _ => unreachable!("run finished without a stop or crash event"),
+        };
+        RunResult {
+            outcome,
+            code,
+            pc: self.pc,
+            events,
+        }
+    }
+
     pub fn run_once(&mut self) {
         self.execute_instruction();
     }
 
+    /// Executes exactly one instruction, returning true if the program has stopped.
+    /// A breakpoint on that instruction doesn't hold it back, stepping is already a pause.
+    pub fn step(&mut self) -> bool {
+        match self.execute_instruction() {
+            Some(VMEventType::BreakpointHit) => self.execute_instruction().is_some(),
+            event => event.is_some(),
+        }
+    }
+
+    /// The offset of the next instruction to execute
+    ///
+    /// ```
+    /// use lrvm::vm::VM;
+    ///
+    /// let mut vm = VM::new();
+    /// // load $0 #100
+    /// vm.program = vec![0, 0, 0, 100];
+    /// vm.run_once();
+    /// assert_eq!(vm.pc(), 4);
+    /// assert_eq!(vm.registers[0], 100);
+    /// ```
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The result of the last comparison, which `JMPE` uses to decide whether to jump
+    pub fn equal_flag(&self) -> bool {
+        self.equal_flag
+    }
+
+    /// The remainder of the last `DIV`
+    pub fn reminder(&self) -> usize {
+        self.reminder
+    }
+
+    /// The counter used by the `LOOP` instruction
+    pub fn loop_counter(&self) -> usize {
+        self.loop_counter
+    }
+
+    pub fn heap(&self) -> &[u8] {
+        &self.heap
+    }
+
+    pub fn heap_len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn stack(&self) -> &[u8] {
+        &self.stack
+    }
+
+    pub fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// The read-only section of the running program, e.g. its `.asciiz` strings
+    pub fn ro_data(&self) -> &[u8] {
+        &self.ro_data
+    }
+
+    /// Captures the registers, pc, flags and memory so they can be restored later
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            float_registers: self.float_registers,
+            pc: self.pc,
+            heap: self.heap.clone(),
+            stack: self.stack.clone(),
+            equal_flag: self.equal_flag,
+            loop_counter: self.loop_counter,
+            reminder: self.reminder,
+            ro_data: self.ro_data.clone(),
+        }
+    }
+
+    /// Puts back the state captured by `snapshot`. The program itself is left as it is.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.registers = snapshot.registers;
+        self.float_registers = snapshot.float_registers;
+        self.pc = snapshot.pc;
+        self.heap = snapshot.heap;
+        self.stack = snapshot.stack;
+        self.equal_flag = snapshot.equal_flag;
+        self.loop_counter = snapshot.loop_counter;
+        self.reminder = snapshot.reminder;
+        self.set_ro_data(snapshot.ro_data);
+    }
+
+    /// Pauses execution whenever the pc reaches `addr`, before that instruction runs
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes a breakpoint, returning whether there was one at `addr`
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Carries on from the current pc, e.g. after pausing at a breakpoint, until the program
+    /// stops or reaches another breakpoint
+    pub fn resume(&mut self) -> RunOutcome {
+        loop {
+            match self.execute_instruction() {
+                None => continue,
+                Some(VMEventType::BreakpointHit) => return RunOutcome::Paused,
+                Some(VMEventType::Crash { .. }) => return RunOutcome::Crashed,
+                Some(VMEventType::InstructionLimitReached) => return RunOutcome::LimitReached,
+                Some(_) => return RunOutcome::Halted,
+            }
+        }
+    }
+
+    /// Executes instructions from the current pc until the program stops. Unlike `run` this
+    /// neither checks the header nor records events, so it suits programs built up in the REPL.
+    pub fn run_to_completion(&mut self) {
+        while self.execute_instruction().is_none() {}
+    }
+
+    /// Like `run_to_completion`, but hands the VM to `f` after every instruction so the caller can
+    /// record or draw its state, e.g. for a visual debugger
+    pub fn run_with_callback(&mut self, mut f: impl FnMut(&VM)) {
+        loop {
+            let done = self.execute_instruction();
+            f(self);
+            if done.is_some() {
+                break;
+            }
+        }
+    }
+
+    /// Moves the pc back to the first instruction and zeroes the instruction count,
+    /// so the loaded program can be run again from the start
+    pub fn rewind(&mut self) {
+        self.pc = self.entry_point();
+        self.instruction_count = 0;
+        self.rand_state = DETERMINISTIC_RAND_SEED;
+        self.paused_at = None;
+    }
+
+    /// Unloads the program and puts the registers, flags, heap and stack back the way `new` left
+    /// them. Settings such as syscalls, limits and cluster configuration are kept.
+    pub fn reset(&mut self) {
+        self.registers = [0; 32];
+        self.float_registers = [0.0; 32];
+        self.program.clear();
+        self.set_ro_data(vec![]);
+        self.heap = vec![0; DEFAULT_HEAP_STARTING_SIZE];
+        self.stack.clear();
+        self.pc = 0;
+        self.loop_counter = 0;
+        self.reminder = 0;
+        self.equal_flag = false;
+        self.events.clear();
+        self.assertions.clear();
+        self.instruction_count = 0;
+        self.rand_state = DETERMINISTIC_RAND_SEED;
+        self.breakpoints.clear();
+        self.paused_at = None;
+    }
+
+    /// Number of instructions executed since the program was last started
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// Writes the registers, pc, flags, heap, stack and last event to `path` as plain text,
+    /// for inspecting a failed program after the fact
+    pub fn dump_core(&self, path: &Path) -> io::Result<()> {
+        let mut dump = String::new();
+        dump.push_str(&format!("id: {}\n", self.id));
+        dump.push_str(&format!("pc: {}\n", self.pc));
+        dump.push_str(&format!("equal_flag: {}\n", self.equal_flag));
+        dump.push_str(&format!("reminder: {}\n", self.reminder));
+        dump.push_str(&format!("loop_counter: {}\n", self.loop_counter));
+        dump.push_str(&format!("registers: {:?}\n", self.registers));
+        dump.push_str(&format!("float_registers: {:?}\n", self.float_registers));
+        dump.push_str(&format!("heap: {:?}\n", self.heap));
+        dump.push_str(&format!("stack: {:?}\n", self.stack));
+        if let Some(event) = self.events.last() {
+            dump.push_str(&format!("event: {:?} at {}\n", event.event, event.at));
+        }
+        fs::write(path, dump)
+    }
+
+    /// Swaps in a new program while keeping the register contents, so an edited program can be
+    /// re-run against the values computed so far. Unlike a full reset, the heap is left alone too.
+    pub fn load_new_program(&mut self, bytes: Vec<u8>) {
+        self.program = bytes;
+        self.set_ro_data(vec![]);
+        self.stack.clear();
+        self.pc = self.entry_point();
+        self.equal_flag = false;
+        self.reminder = 0;
+        self.loop_counter = 0;
+    }
+
+    /// Decodes every instruction of the code section without executing anything, and reports the
+    /// offset and byte of each opcode that is illegal. Lets a host reject untrusted bytecode up front.
+    pub fn validate_program(&self) -> Result<(), Vec<(usize, u8)>> {
+        let illegal: Vec<(usize, u8)> = self
+            .instructions()
+            .into_iter()
+            .filter(|(_, opcode)| *opcode == Opcode::IGL)
+            .map(|(offset, _)| (offset, self.program[offset]))
+            .collect();
+
+        if illegal.is_empty() {
+            Ok(())
+        } else {
+            Err(illegal)
+        }
+    }
+
+    /// Walks the code section without executing it, giving the offset and opcode of each instruction
+    pub fn instructions(&self) -> Vec<(usize, Opcode)> {
+        let mut instructions = vec![];
+        let mut offset = self.entry_point();
+        while offset < self.program.len() {
+            let opcode = Opcode::from(self.program[offset]);
+            instructions.push((offset, opcode));
+            offset += opcode.instruction_len();
+        }
+        instructions
+    }
+
+    /// Decodes the code section back into assembly, one line per instruction along with its offset.
+    /// Bytes that aren't a known opcode come out as `igl` with the byte that was found.
+    pub fn disassemble(&self) -> Vec<(usize, String)> {
+        self.instructions()
+            .into_iter()
+            .map(|(offset, opcode)| {
+                let end = (offset + opcode.instruction_len()).min(self.program.len());
+                let text = match opcode {
+                    Opcode::IGL => format!("igl ; illegal opcode {}", self.program[offset]),
+                    _ => opcode.disassemble(&self.program[offset..end]),
+                };
+                (offset, text)
+            })
+            .collect()
+    }
+
+    /// The offset of the first instruction to execute: right after the header for programs that
+    /// have one, otherwise the very first byte
+    fn entry_point(&self) -> usize {
+        if self.verify_header() {
+            PIE_HEADER_LENGTH + 4 + self.ro_length()
+        } else {
+            0
+        }
+    }
+
+    /// The length of the read-only section, as recorded in the header right after the magic
+    /// prefix. The code starts right after the read-only section.
+    fn ro_length(&self) -> usize {
+        match self
+            .program
+            .get(PIE_HEADER_RO_LENGTH_OFFSET..PIE_HEADER_RO_LENGTH_OFFSET + 4)
+        {
+            Some(bytes) => Cursor::new(bytes).read_u32::<BigEndian>().unwrap() as usize,
+            None => 0,
+        }
+    }
+
+    /// The lowest VM version the program's header says it can run on
+    fn required_version(&self) -> u32 {
+        match self
+            .program
+            .get(PIE_HEADER_VERSION_OFFSET..PIE_HEADER_VERSION_OFFSET + 4)
+        {
+            Some(bytes) => Cursor::new(bytes).read_u32::<BigEndian>().unwrap(),
+            None => 0,
+        }
+    }
+
+    /// The raw header bytes at the start of the program, or `None` if the program is too short
+    /// to have a header
+    pub fn header(&self) -> Option<&[u8]> {
+        self.program.get(..PIE_HEADER_LENGTH)
+    }
+
+    /// Everything `run` needs from the header before it can start: the prefix, the whole header,
+    /// a version this VM supports and a read-only section that fits in the program
+    fn check_header(&self) -> Result<(), HeaderError> {
+        if self.program.is_empty() {
+            return Err(HeaderError::Empty);
+        }
+        if !self.program.starts_with(&PIE_HEADER_PREFIX) {
+            return Err(HeaderError::MissingPrefix);
+        }
+        if !self.verify_header() {
+            return Err(HeaderError::Truncated {
+                len: self.program.len(),
+            });
+        }
+        let required = self.required_version();
+        if required > VM_VERSION {
+            return Err(HeaderError::UnsupportedVersion { required });
+        }
+        let ro_length = self.ro_length();
+        if PIE_HEADER_LENGTH + 4 + ro_length > self.program.len() {
+            return Err(HeaderError::RoSectionOutOfBounds { ro_length });
+        }
+        Ok(())
+    }
+
+    /// Checks that the program starts with a whole header, including the 4 byte slot after it,
+    /// and that the header begins with the magic prefix
     fn verify_header(&self) -> bool {
-        self.program[0..4] == PIE_HEADER_PREFIX
+        self.program.len() >= PIE_HEADER_LENGTH + 4 && self.program[0..4] == PIE_HEADER_PREFIX
     }
 
     pub fn add_byte(&mut self, byte: u8) {
@@ -155,12 +915,137 @@ impl VM {
         self.program.append(&mut bytes);
     }
 
-    fn execute_instruction(&mut self) -> Option<u32> {
+    /// Adds register checks (usually the assembler's `.assert_reg` list) to verify after `run`
+    pub fn add_assertions(&mut self, mut assertions: Vec<RegisterAssertion>) {
+        self.assertions.append(&mut assertions);
+    }
+
+    /// Returns every assertion that doesn't hold right now, along with the register's actual value
+    pub fn check_assertions(&self) -> Vec<(RegisterAssertion, i32)> {
+        self.assertions
+            .iter()
+            .map(|a| (a.clone(), self.registers[a.register as usize]))
+            .filter(|(a, actual)| a.expected != *actual)
+            .collect()
+    }
+
+    fn report_assertions(&self) {
+        let failures = self.check_assertions();
+        for (assertion, actual) in &failures {
+            display::e_writeout(&format!(
+                "Assertion failed: ${} should be {} but was {}",
+                assertion.register, assertion.expected, actual
+            ));
+        }
+        display::writeout(&format!(
+            "{} of {} assertions passed",
+            self.assertions.len() - failures.len(),
+            self.assertions.len()
+        ));
+    }
+
+    /// The opcodes this VM can actually execute
+    pub fn implemented_opcodes() -> Vec<Opcode> {
+        IMPLEMENTED_OPCODES.to_vec()
+    }
+
+    /// The opcodes that are defined in the instruction set but can't be executed yet
+    pub fn unimplemented_opcodes() -> Vec<Opcode> {
+        (0..=u8::MAX)
+            .map(Opcode::from)
+            .filter(|opcode| *opcode != Opcode::IGL && !IMPLEMENTED_OPCODES.contains(opcode))
+            .collect()
+    }
+
+    /// Registers a host function that programs can invoke with `SYSCALL #number`.
+    /// Registering the same number twice replaces the earlier handler.
+    pub fn register_syscall<F>(&mut self, number: u16, handler: F)
+    where
+        F: Fn(&mut VM) + Send + Sync + 'static,
+    {
+        self.syscalls.handlers.insert(number, Arc::new(handler));
+    }
+
+    /// Makes `opcode` run `handler` instead of its built-in behaviour. This can give meaning to an
+    /// opcode byte the VM doesn't define, or replace what an existing one (even `IGL`) does.
+    pub fn register_handler<H>(&mut self, opcode: impl Into<u8>, handler: H)
+    where
+        H: OpcodeHandler + 'static,
+    {
+        self.handlers.entries[opcode.into() as usize] = Some(Handler::Custom(Arc::new(handler)));
+    }
+
+    /// Executes one instruction, returning the event that ends the run if this instruction ended it
+    fn execute_instruction(&mut self) -> Option<VMEventType> {
         if self.pc >= self.program.len() {
-            return Some(1);
+            return Some(VMEventType::GracefulStop { code: 1 });
+        }
+        // Checking for emptiness first keeps the hash lookup out of runs without breakpoints
+        if !self.breakpoints.is_empty()
+            && self.breakpoints.contains(&self.pc)
+            && self.paused_at != Some(self.pc)
+        {
+            self.paused_at = Some(self.pc);
+            return Some(VMEventType::BreakpointHit);
+        }
+        self.paused_at = None;
+        if let Some(limit) = self.max_instructions {
+            if self.instruction_count >= limit {
+                display::e_writeout(&format!("Stopped after {} instructions", limit));
+                return Some(VMEventType::InstructionLimitReached);
+            }
         }
+        self.instruction_count += 1;
 
-        match self.decode_opcode() {
+        let start = self.pc;
+        #[cfg(debug_assertions)]
+        if self.check_instruction_width {
+            let entry = self.entry_point();
+            assert!(
+                start < entry || (start - entry).is_multiple_of(4),
+                "misaligned instruction: pc {} is not on a 4 byte boundary from {}",
+                start,
+                entry
+            );
+        }
+
+        // The table already knows which opcode a byte is, so the byte is decoded only once
+        let byte = self.next_8_bits();
+        let opcode = match &self.handlers.entries[byte as usize] {
+            Some(Handler::Builtin(opcode)) => *opcode,
+            Some(Handler::Custom(handler)) => {
+                // Cloned out of the table so the handler can borrow the VM mutably
+                let handler = Arc::clone(handler);
+                handler.execute(self);
+                return None;
+            },
+            None => {
+                self.skip_unimplemented(Opcode::from(byte));
+                return None;
+            },
+        };
+        if let Some(event) = self.execute_builtin(opcode) {
+            return Some(event);
+        }
+
+        #[cfg(debug_assertions)]
+        if self.check_instruction_width && !opcode.is_jump() {
+            assert_eq!(
+                self.pc - start,
+                opcode.instruction_len(),
+                "{:?} at pc {} consumed the wrong number of bytes",
+                opcode,
+                start
+            );
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = start;
+        None
+    }
+
+    /// Runs one of the VM's own opcodes, with the pc just past the opcode byte
+    fn execute_builtin(&mut self, opcode: Opcode) -> Option<VMEventType> {
+        match opcode {
             Opcode::LOAD => {
                 let register = self.next_8_bits() as usize; // convert it to usize as the indexer of registers' array
                 let number = self.next_16_bits(); // get the next 16 bits where store the number ready to store in the register
@@ -170,40 +1055,170 @@ impl VM {
             Opcode::ADD => {
                 let register1 = self.registers[self.next_8_bits() as usize];
                 let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 + register2;
+                let dst = self.next_8_bits() as usize;
+                match self.integer_op(register1, register2, i32::checked_add, i32::wrapping_add) {
+                    Some(value) => self.registers[dst] = value,
+                    None => return self.overflow("ADD", register1, register2),
+                }
+            },
+            Opcode::AND => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                self.registers[self.next_8_bits() as usize] = register1 & register2;
+            },
+            Opcode::OR => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                self.registers[self.next_8_bits() as usize] = register1 | register2;
+            },
+            Opcode::XOR => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                self.registers[self.next_8_bits() as usize] = register1 ^ register2;
+            },
+            Opcode::NOT => {
+                let register = self.registers[self.next_8_bits() as usize];
+                self.registers[self.next_8_bits() as usize] = !register;
+                // Skip the padding byte
+                self.pc += 1;
+            },
+            Opcode::INC => {
+                let register = self.next_8_bits() as usize;
+                // Skip the two padding bytes
+                self.pc += 2;
+                let value = self.registers[register];
+                match self.integer_op(value, 1, i32::checked_add, i32::wrapping_add) {
+                    Some(value) => self.registers[register] = value,
+                    None => return self.overflow("INC", value, 1),
+                }
+            },
+            Opcode::DEC => {
+                let register = self.next_8_bits() as usize;
+                // Skip the two padding bytes, the same as INC
+                self.pc += 2;
+                let value = self.registers[register];
+                match self.integer_op(value, 1, i32::checked_sub, i32::wrapping_sub) {
+                    Some(value) => self.registers[register] = value,
+                    None => return self.overflow("DEC", value, 1),
+                }
+            },
+            Opcode::ADDI => {
+                let dst = self.next_8_bits() as usize;
+                let register = self.registers[self.next_8_bits() as usize];
+                // The immediate is a signed 8 bit value, the only room left in the instruction
+                let immediate = self.next_8_bits() as i8 as i32;
+                match self.integer_op(register, immediate, i32::checked_add, i32::wrapping_add) {
+                    Some(value) => self.registers[dst] = value,
+                    None => return self.overflow("ADDI", register, immediate),
+                }
             },
             Opcode::SUB => {
                 let register1 = self.registers[self.next_8_bits() as usize];
                 let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 - register2;
+                let dst = self.next_8_bits() as usize;
+                match self.integer_op(register1, register2, i32::checked_sub, i32::wrapping_sub) {
+                    Some(value) => self.registers[dst] = value,
+                    None => return self.overflow("SUB", register1, register2),
+                }
             },
             Opcode::MUL => {
                 let register1 = self.registers[self.next_8_bits() as usize];
                 let register2 = self.registers[self.next_8_bits() as usize];
                 debug!("register1:{:?}, register2:{:?}", register1, register2);
-                self.registers[self.next_8_bits() as usize] = register1 * register2;
+                let dst = self.next_8_bits() as usize;
+                match self.integer_op(register1, register2, i32::checked_mul, i32::wrapping_mul) {
+                    Some(value) => self.registers[dst] = value,
+                    None => return self.overflow("MUL", register1, register2),
+                }
             },
             Opcode::DIV => {
                 let register1 = self.registers[self.next_8_bits() as usize];
                 let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 / register2;
-                self.reminder = (register1 % register2) as usize;
+                let dst = self.next_8_bits() as usize;
+                // Both the quotient and the remainder would panic on a zero divisor
+                if register2 == 0 {
+                    return self.crash(
+                        CRASH_DIVIDE_BY_ZERO,
+                        &format!("DIV of {} by zero", register1),
+                    );
+                }
+                // i32::MIN / -1 is the one quotient that doesn't fit
+                match self.integer_op(register1, register2, i32::checked_div, i32::wrapping_div) {
+                    Some(value) => self.registers[dst] = value,
+                    None => return self.overflow("DIV", register1, register2),
+                }
+                self.reminder = register1.wrapping_rem(register2) as usize;
+            },
+            Opcode::MOD => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                let dst = self.next_8_bits() as usize;
+                if register2 == 0 {
+                    return self.crash(
+                        CRASH_DIVIDE_BY_ZERO,
+                        &format!("MOD of {} by zero", register1),
+                    );
+                }
+                // As with DIV, i32::MIN % -1 overflows; the sign follows the dividend
+                match self.integer_op(register1, register2, i32::checked_rem, i32::wrapping_rem) {
+                    Some(value) => self.registers[dst] = value,
+                    None => return self.overflow("MOD", register1, register2),
+                }
+            },
+            Opcode::MOV => {
+                // mov $dst $src, integer registers only; float registers have no MOV counterpart
+                let dst = self.next_8_bits() as usize;
+                let src = self.registers[self.next_8_bits() as usize];
+                self.registers[dst] = src;
+                self.next_8_bits();
             },
             Opcode::HLT => {
                 info!("Hit the HLT");
-                return Some(0);
+                return Some(VMEventType::GracefulStop { code: 0 });
             },
             Opcode::IGL => {
                 display::e_writeout("Illegal instruction encountered");
-                return Some(1);
+                return Some(VMEventType::GracefulStop { code: 1 });
             },
             Opcode::JMP => {
                 let target = self.registers[self.next_8_bits() as usize];
                 self.pc = target as usize;
             },
+            Opcode::CALL => {
+                let target = self.registers[self.next_8_bits() as usize];
+                // Skip the padding so the saved pc is the instruction after the CALL
+                self.pc += 2;
+                if self.stack.len() + 4 > MAX_STACK_SIZE {
+                    return self.crash(CRASH_STACK_OVERFLOW, "CALL would overflow the stack");
+                }
+                self.push_i32(self.pc as i32);
+                self.pc = target as usize;
+            },
+            Opcode::RET => {
+                if self.stack.len() < 4 {
+                    return self.crash(
+                        CRASH_STACK_UNDERFLOW,
+                        "RET with no return address on the stack",
+                    );
+                }
+                self.pc = self.pop_i32() as usize;
+            },
             Opcode::JMPF => {
                 let value = self.registers[self.next_8_bits() as usize];
-                self.pc += value as usize;
+                // Running past the end would look like the program finishing, so it is a crash instead
+                let target = usize::try_from(value)
+                    .ok()
+                    .and_then(|value| self.pc.checked_add(value))
+                    .filter(|target| *target < self.program.len());
+                match target {
+                    Some(target) => self.pc = target,
+                    None => {
+                        return self.crash(
+                            CRASH_JUMP_OUT_OF_BOUNDS,
+                            &format!("JMPF by {} from {} leaves the program", value, self.pc),
+                        );
+                    },
+                }
             },
             Opcode::JMPB => {
                 let value = self.registers[self.next_8_bits() as usize];
@@ -251,7 +1266,8 @@ impl VM {
                 if self.equal_flag {
                     self.pc = target as usize;
                 } else {
-                    // TODO: Fix the bits
+                    // Not taken, so skip the padding and carry on with the next instruction
+                    self.pc += 2;
                 }
             },
             Opcode::ALOC => {
@@ -259,23 +1275,42 @@ impl VM {
                 let bytes = self.registers[register];
                 let new_end = self.heap.len() as i32 + bytes;
                 self.heap.resize(new_end as usize, 0);
+                self.next_16_bits();
+            },
+            Opcode::LUI => {
+                let register = self.next_8_bits() as usize;
+                let upper = self.next_16_bits() as u32;
+                // The low half is kept, so LOAD followed by LUI builds a full 32 bit value
+                let lower = self.registers[register] as u32 & 0xFFFF;
+                self.registers[register] = ((upper << 16) | lower) as i32;
+            },
+            Opcode::HEAPSZ => {
+                let register = self.next_8_bits() as usize;
+                self.registers[register] = self.heap.len() as i32;
+                self.next_16_bits();
             },
             Opcode::PRTS => {
                 // PRTS 需要一个操作数，要么是字节码的只读部分中的起始索引
                 // 或者是一个符号（以 @symbol_name 的形式），它将在符号表中查找偏移量。
                 // 这条指令然后读取每个字节并打印它，直到它遇到一个 0x00 字节，这表示字符串的终止
                 let starting_offset = self.next_16_bits() as usize;
-                let mut ending_offset = starting_offset;
-                let slice = self.ro_data.as_slice();
+                self.next_8_bits();
 
-                // TODO: 是否能够找到一个更好的方法来做这个。也许我们可以存储字节长度而不是空终止？
-                // 或者某种形式的缓存，我们在 VM 启动时就通过整个 ro_data 并找到每个字符串及其结束字节位置？
-                while slice[ending_offset] != 0 {
-                    ending_offset += 1;
-                }
-                let result = std::str::from_utf8(&slice[starting_offset..ending_offset]);
+                // 找不到终止符就崩溃
+                let bytes = match self.ro_string(starting_offset) {
+                    Some(bytes) => bytes,
+                    None => {
+                        return self.crash(
+                            CRASH_RO_OUT_OF_BOUNDS,
+                            &format!(
+                                "PRTS found no terminated string at {} in the read-only section",
+                                starting_offset
+                            ),
+                        );
+                    },
+                };
 
-                match result {
+                match std::str::from_utf8(bytes) {
                     Ok(s) => {
                         print!("{}", s);
                     },
@@ -284,6 +1319,13 @@ impl VM {
                     },
                 }
             },
+            Opcode::PRTI => {
+                // 和 PRTS 一样直接写到 stdout，不带换行
+                let value = self.registers[self.next_8_bits() as usize];
+                print!("{}", value);
+                // Skip the two padding bytes
+                self.next_16_bits();
+            },
             Opcode::LOADF64 => {
                 let register = self.next_8_bits() as usize;
                 let num = f64::from(self.next_16_bits());
@@ -355,6 +1397,7 @@ impl VM {
                     0 => 16,        // If it is 0, it defaults to 16 bits
                     other => other, // If it is some other number, it shifts that amount
                 };
+                self.next_8_bits();
                 self.registers[reg_num] = self.registers[reg_num].wrapping_shl(num_bits.into());
             },
             Opcode::SHR => {
@@ -364,54 +1407,418 @@ impl VM {
                     0 => 16,        // If it is 0, it defaults to 16 bits
                     other => other, // If it is some other number, it shifts that amount
                 };
+                self.next_8_bits();
                 self.registers[reg_num] = self.registers[reg_num].wrapping_shr(num_bits.into());
             },
-            Opcode::AND => {},
-            _ => display::e_writeout(&format!(
-                "Unknown opcode:{:?} has not been impl;",
-                self.decode_opcode()
-            )),
-        }
-        None
-    }
-
-    fn get_starting_offset(&self) -> usize {
-        // We only want to read the slice containing the 4 bytes right after the magic number
-        let mut rdr = Cursor::new(&self.program[64..68]);
-        // Read it as a u32, cast as a usize (since the VM's PC attribute is a usize), and return it
-        rdr.read_u32::<LittleEndian>().unwrap() as usize
-    }
-
-    pub fn with_alias(mut self, alias: String) -> Self {
-        if alias.is_empty() {
-            self.alias = None;
-        } else {
-            self.alias = Some(alias);
-        }
-        self
-    }
-
-    pub fn with_cluster_bind(mut self, server_addr: String, server_port: String) -> Self {
-        display::writeout(&format!("Binding VM to {}:{}", server_addr, server_port));
-        self.server_addr = Some(server_addr);
-        self.server_port = Some(server_port);
+            Opcode::ROL => {
+                let reg_num = self.next_8_bits() as usize;
+                // Rotating by the 16 bit immediate, reduced modulo 32 by rotate_left itself
+                let num_bits = u32::from(self.next_16_bits());
+                self.registers[reg_num] =
+                    (self.registers[reg_num] as u32).rotate_left(num_bits) as i32;
+            },
+            Opcode::ROR => {
+                let reg_num = self.next_8_bits() as usize;
+                let num_bits = u32::from(self.next_16_bits());
+                self.registers[reg_num] =
+                    (self.registers[reg_num] as u32).rotate_right(num_bits) as i32;
+            },
+            Opcode::CLZ => {
+                let dst = self.next_8_bits() as usize;
+                let src = self.registers[self.next_8_bits() as usize];
+                self.registers[dst] = (src as u32).leading_zeros() as i32;
+                self.next_8_bits();
+            },
+            Opcode::POPCNT => {
+                let dst = self.next_8_bits() as usize;
+                let src = self.registers[self.next_8_bits() as usize];
+                self.registers[dst] = (src as u32).count_ones() as i32;
+                self.next_8_bits();
+            },
+            Opcode::RAND => {
+                let register = self.next_8_bits() as usize;
+                self.next_16_bits();
+                self.registers[register] = if self.deterministic {
+                    // xorshift32, so the sequence only depends on how many numbers were drawn
+                    self.rand_state ^= self.rand_state << 13;
+                    self.rand_state ^= self.rand_state >> 17;
+                    self.rand_state ^= self.rand_state << 5;
+                    self.rand_state as i32
+                } else {
+                    let bytes = Uuid::new_v4().into_bytes();
+                    i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                };
+            },
+            Opcode::TIME => {
+                let register = self.next_8_bits() as usize;
+                self.next_16_bits();
+                // Seconds since the Unix epoch, or always 0 when running deterministically
+                self.registers[register] = if self.deterministic {
+                    0
+                } else {
+                    Utc::now().timestamp() as i32
+                };
+            },
+            Opcode::READI => {
+                let register = self.next_8_bits() as usize;
+                self.next_16_bits();
+                if self.deterministic {
+                    return self.crash(
+                        CRASH_NONDETERMINISTIC,
+                        "READI can't be used in deterministic mode",
+                    );
+                }
+                let mut line = String::new();
+                self.registers[register] = match io::stdin().read_line(&mut line) {
+                    Ok(_) => line.trim().parse().unwrap_or(0),
+                    Err(_) => 0,
+                };
+            },
+            Opcode::MIN => {
+                let dst = self.next_8_bits() as usize;
+                let a = self.registers[self.next_8_bits() as usize];
+                let b = self.registers[self.next_8_bits() as usize];
+                self.registers[dst] = cmp::min(a, b);
+            },
+            Opcode::MAX => {
+                let dst = self.next_8_bits() as usize;
+                let a = self.registers[self.next_8_bits() as usize];
+                let b = self.registers[self.next_8_bits() as usize];
+                self.registers[dst] = cmp::max(a, b);
+            },
+            Opcode::MINF64 => {
+                let dst = self.next_8_bits() as usize;
+                let a = self.float_registers[self.next_8_bits() as usize];
+                let b = self.float_registers[self.next_8_bits() as usize];
+                // f64::min ignores a NaN operand and returns the other one
+                self.float_registers[dst] = a.min(b);
+            },
+            Opcode::MAXF64 => {
+                let dst = self.next_8_bits() as usize;
+                let a = self.float_registers[self.next_8_bits() as usize];
+                let b = self.float_registers[self.next_8_bits() as usize];
+                self.float_registers[dst] = a.max(b);
+            },
+            Opcode::CLAMPF64 => {
+                let register = self.next_8_bits() as usize;
+                let lo = self.float_registers[self.next_8_bits() as usize];
+                let hi = self.float_registers[self.next_8_bits() as usize];
+                // A NaN bound can't be compared, so it counts as an invalid range too
+                if lo.is_nan() || hi.is_nan() || lo > hi {
+                    return self.crash(
+                        CRASH_INVALID_RANGE,
+                        &format!("CLAMPF64 range is invalid: {} to {}", lo, hi),
+                    );
+                }
+                // A NaN value stays NaN
+                self.float_registers[register] = self.float_registers[register].clamp(lo, hi);
+            },
+            Opcode::CLAMP => {
+                let register = self.next_8_bits() as usize;
+                let lo = self.registers[self.next_8_bits() as usize];
+                let hi = self.registers[self.next_8_bits() as usize];
+                // An inverted range is a bug in the program, so it crashes rather than being swapped
+                if lo > hi {
+                    return self.crash(
+                        CRASH_INVALID_RANGE,
+                        &format!("CLAMP range is inverted: {} > {}", lo, hi),
+                    );
+                }
+                self.registers[register] = self.registers[register].clamp(lo, hi);
+            },
+            Opcode::PUSHA => {
+                // No operands, just skip the padding
+                self.pc += 3;
+                if self.stack.len() + self.registers.len() * 4 > MAX_STACK_SIZE {
+                    return self.crash(CRASH_STACK_OVERFLOW, "PUSHA would overflow the stack");
+                }
+                for i in 0..self.registers.len() {
+                    self.push_i32(self.registers[i]);
+                }
+            },
+            Opcode::POPA => {
+                self.pc += 3;
+                if self.stack.len() < self.registers.len() * 4 {
+                    return self.crash(
+                        CRASH_STACK_UNDERFLOW,
+                        "POPA needs more bytes than the stack holds",
+                    );
+                }
+                // Registers come back off in the reverse order PUSHA put them on
+                for i in (0..self.registers.len()).rev() {
+                    self.registers[i] = self.pop_i32();
+                }
+            },
+            Opcode::LOAD32 => {
+                // [LOAD32, reg, pad, pad] followed by a whole slot holding the big endian immediate,
+                // so the pc ends up 8 bytes past where the instruction started
+                let register = self.next_8_bits() as usize;
+                self.next_16_bits();
+                let high = self.next_16_bits() as u32;
+                let low = self.next_16_bits() as u32;
+                self.registers[register] = ((high << 16) | low) as i32;
+            },
+            Opcode::LOADMO => {
+                let dst = self.next_8_bits() as usize;
+                let base = self.registers[self.next_8_bits() as usize];
+                let offset = self.next_8_bits() as i8 as i32;
+                let range = base
+                    .checked_add(offset)
+                    .and_then(|addr| self.heap_range(addr, 4));
+                match range {
+                    Some(range) => {
+                        let mut word = [0; 4];
+                        word.copy_from_slice(&self.heap[range]);
+                        self.registers[dst] = i32::from_be_bytes(word);
+                    },
+                    None => {
+                        return self.crash(
+                            CRASH_HEAP_OUT_OF_BOUNDS,
+                            &format!("LOADMO at {}{:+} is outside the heap", base, offset),
+                        );
+                    },
+                }
+            },
+            Opcode::TAS => {
+                // Test-and-set: the old word goes to the register, the flag says whether it was
+                // free (0), and the word is left set to 1, all in one instruction
+                let dst = self.next_8_bits() as usize;
+                let addr = self.registers[self.next_8_bits() as usize];
+                self.pc += 1;
+                let range = match self.heap_range(addr, 4) {
+                    Some(range) => range,
+                    None => {
+                        return self.crash(
+                            CRASH_HEAP_OUT_OF_BOUNDS,
+                            &format!("TAS at {} is outside the heap", addr),
+                        );
+                    },
+                };
+                let mut word = [0; 4];
+                word.copy_from_slice(&self.heap[range.clone()]);
+                let old = i32::from_be_bytes(word);
+                self.heap[range].copy_from_slice(&1i32.to_be_bytes());
+                self.registers[dst] = old;
+                self.equal_flag = old == 0;
+            },
+            Opcode::FILL => {
+                let addr = self.registers[self.next_8_bits() as usize];
+                let len = self.registers[self.next_8_bits() as usize];
+                // Only the low byte of the value register is written
+                let byte = self.registers[self.next_8_bits() as usize] as u8;
+                match self.heap_range(addr, len) {
+                    Some(range) => self.heap[range].fill(byte),
+                    None => {
+                        return self.crash(
+                            CRASH_HEAP_OUT_OF_BOUNDS,
+                            &format!("FILL of {} bytes at {} is outside the heap", len, addr),
+                        );
+                    },
+                }
+            },
+            Opcode::COPYRO => {
+                let heap_addr = self.registers[self.next_8_bits() as usize];
+                let ro_offset = self.registers[self.next_8_bits() as usize];
+                let len = self.registers[self.next_8_bits() as usize];
+                let ro_range = match ro_offset.checked_add(len) {
+                    Some(end)
+                        if ro_offset >= 0 && len >= 0 && end as usize <= self.ro_data.len() =>
+                    {
+                        ro_offset as usize..end as usize
+                    },
+                    _ => {
+                        return self.crash(
+                            CRASH_RO_OUT_OF_BOUNDS,
+                            &format!(
+                                "COPYRO of {} bytes from {} is outside the read-only section",
+                                len, ro_offset
+                            ),
+                        );
+                    },
+                };
+                match self.heap_range(heap_addr, len) {
+                    Some(range) => self.heap[range].copy_from_slice(&self.ro_data[ro_range]),
+                    None => {
+                        return self.crash(
+                            CRASH_HEAP_OUT_OF_BOUNDS,
+                            &format!(
+                                "COPYRO of {} bytes to {} is outside the heap",
+                                len, heap_addr
+                            ),
+                        );
+                    },
+                }
+            },
+            Opcode::SYSCALL => {
+                let number = self.next_16_bits();
+                self.next_8_bits();
+                // Clone the handler out of the table so it can borrow the VM mutably
+                match self.syscalls.handlers.get(&number).cloned() {
+                    Some(handler) => handler(self),
+                    None => {
+                        return self.crash(
+                            CRASH_UNKNOWN_SYSCALL,
+                            &format!("No handler registered for syscall #{}", number),
+                        );
+                    },
+                }
+            },
+            _ => self.skip_unimplemented(opcode),
+        }
+        None
+    }
+
+    /// Replaces the read-only section and works out where each of its strings ends
+    fn set_ro_data(&mut self, ro_data: Vec<u8>) {
+        self.ro_string_ends.clear();
+        let mut start = 0;
+        for (i, byte) in ro_data.iter().enumerate() {
+            if *byte == 0 {
+                self.ro_string_ends.insert(start, i);
+                start = i + 1;
+            }
+        }
+        self.ro_data = ro_data;
+    }
+
+    /// The bytes of the null terminated string starting at `offset` in the read-only section,
+    /// without the terminator. `None` if there is no terminator before the end of the section.
+    pub fn ro_string(&self, offset: usize) -> Option<&[u8]> {
+        match self.ro_string_ends.get(&offset) {
+            Some(end) => self.ro_data.get(offset..*end),
+            // Not the start of a string, e.g. somewhere inside one, so fall back to scanning
+            None => self.scan_ro_string(offset),
+        }
+    }
+
+    /// Finds the end of the string at `offset` by looking for its terminator byte by byte
+    fn scan_ro_string(&self, offset: usize) -> Option<&[u8]> {
+        let rest = self.ro_data.get(offset..)?;
+        let len = rest.iter().position(|b| *b == 0)?;
+        Some(&rest[..len])
+    }
+
+    /// Reports an opcode with nothing to run it, with the pc just past the opcode byte
+    fn skip_unimplemented(&mut self, opcode: Opcode) {
+        display::e_writeout(&format!("Unknown opcode:{:?} has not been impl;", opcode));
+        // Skip the operands so the next instruction is still decoded from its first byte
+        self.pc += 3;
+    }
+
+    /// Turns a start address and length taken from registers into a range of the heap,
+    /// or `None` if any part of it falls outside the heap
+    fn heap_range(&self, start: i32, len: i32) -> Option<Range<usize>> {
+        if start < 0 || len < 0 {
+            return None;
+        }
+        let start = start as usize;
+        let end = start.checked_add(len as usize)?;
+        if end > self.heap.len() {
+            return None;
+        }
+        Some(start..end)
+    }
+
+    /// Pushes a 4 byte big endian value onto the stack. Callers check for room first.
+    fn push_i32(&mut self, value: i32) {
+        self.stack.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Pops a value pushed by `push_i32`. Callers check the stack holds one first.
+    fn pop_i32(&mut self) -> i32 {
+        let start = self.stack.len() - 4;
+        let mut word = [0; 4];
+        word.copy_from_slice(&self.stack[start..]);
+        self.stack.truncate(start);
+        i32::from_be_bytes(word)
+    }
+
+    /// Applies an integer operation that can overflow. With `wrapping_arithmetic` the result wraps
+    /// around, otherwise an overflow gives `None` so the instruction can crash.
+    fn integer_op(
+        &self,
+        a: i32,
+        b: i32,
+        checked: fn(i32, i32) -> Option<i32>,
+        wrapping: fn(i32, i32) -> i32,
+    ) -> Option<i32> {
+        if self.wrapping_arithmetic {
+            Some(wrapping(a, b))
+        } else {
+            checked(a, b)
+        }
+    }
+
+    fn overflow(&self, op: &str, a: i32, b: i32) -> Option<VMEventType> {
+        self.crash(
+            CRASH_ARITHMETIC_OVERFLOW,
+            &format!("{} of {} and {} overflowed", op, a, b),
+        )
+    }
+
+    /// Reports why the VM is stopping and produces the matching crash event
+    fn crash(&self, code: u32, msg: &str) -> Option<VMEventType> {
+        display::e_writeout(msg);
+        Some(VMEventType::Crash { code })
+    }
+
+    /// Makes `RAND` and `TIME` reproducible and `READI` crash, e.g. for nodes that have to agree
+    /// on a program's result
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
         self
     }
-    fn decode_opcode(&mut self) -> Opcode {
-        let opcode = Opcode::from(self.program[self.pc]);
-        self.pc += 1;
-        opcode
+
+    /// Stops a run once it has executed `limit` instructions, so a program stuck in a loop
+    /// can't hold the thread forever
+    pub fn with_instruction_limit(mut self, limit: u64) -> Self {
+        self.max_instructions = Some(limit);
+        self
+    }
+
+    /// Lets integer arithmetic wrap around on overflow instead of crashing the program
+    pub fn with_wrapping_arithmetic(mut self, wrapping: bool) -> Self {
+        self.wrapping_arithmetic = wrapping;
+        self
+    }
+
+    /// Turns the debug build instruction width checks on or off. They are on by default.
+    pub fn with_instruction_width_check(mut self, check: bool) -> Self {
+        self.check_instruction_width = check;
+        self
+    }
+
+    pub fn with_alias(mut self, alias: String) -> Self {
+        if alias.is_empty() {
+            self.alias = None;
+        } else {
+            self.alias = Some(alias);
+        }
+        self
+    }
+
+    /// Makes `run` write a core dump to `path` whenever the program crashes
+    pub fn with_core_dump_path(mut self, path: PathBuf) -> Self {
+        self.core_dump_path = Some(path);
+        self
     }
 
-    // Attempts to decode the next byte into an opcode
-    fn next_8_bits(&mut self) -> u8 {
+    pub fn with_cluster_bind(mut self, server_addr: String, server_port: String) -> Self {
+        display::writeout(&format!("Binding VM to {}:{}", server_addr, server_port));
+        self.server_addr = Some(server_addr);
+        self.server_port = Some(server_port);
+        self
+    }
+
+    /// Reads the next byte of the program and moves the pc past it
+    pub fn next_8_bits(&mut self) -> u8 {
         let result = self.program[self.pc];
         self.pc += 1;
         result
     }
 
-    // Grabs the next 16 bits (2 bytes)
-    fn next_16_bits(&mut self) -> u16 {
+    /// Reads the next 2 bytes of the program as a big endian u16 and moves the pc past them
+    pub fn next_16_bits(&mut self) -> u16 {
         let result = (u16::from(self.program[self.pc]) << 8) | u16::from(self.program[self.pc + 1]);
         self.pc += 2;
         result
@@ -425,8 +1832,11 @@ impl VM {
                 display::writeout(&format!("SocketAddr is: {:?}", socket_addr));
 
                 let clone_manager = self.connection_manager.clone();
+                let alias = self.alias.clone().unwrap_or_else(|| self.id.to_string());
                 thread::spawn(move || {
-                    cluster::server::listen(socket_addr, clone_manager);
+                    if let Err(e) = cluster::server::listen(socket_addr, alias, clone_manager) {
+                        display::e_writeout(&format!("Cluster server stopped: {}", e));
+                    }
                 });
             } else {
                 display::e_writeout(&format!(
@@ -448,14 +1858,26 @@ impl VM {
 mod tests {
     use std::vec;
 
+    use chrono::DateTime;
     use log::debug;
 
     use crate::{
-        assembler::prepend_header,
-        vm::{get_test_vm, DEFAULT_HEAP_STARTING_SIZE},
+        assembler::{
+            prepend_header, program_parser::program, symbols::SymbolTable, Assembler,
+            PIE_HEADER_LENGTH, PIE_HEADER_PREFIX,
+        },
+        linker::{link, Object},
+        vm::{
+            events_to_json, get_test_vm, run_program, HeaderError, RunOutcome, VMEventType,
+            CRASH_ARITHMETIC_OVERFLOW, CRASH_BAD_HEADER, CRASH_DIVIDE_BY_ZERO,
+            CRASH_HEAP_OUT_OF_BOUNDS, CRASH_INVALID_RANGE, CRASH_JUMP_OUT_OF_BOUNDS,
+            CRASH_NONDETERMINISTIC, CRASH_NO_PROGRAM, CRASH_RO_OUT_OF_BOUNDS,
+            CRASH_STACK_UNDERFLOW, CRASH_UNKNOWN_SYSCALL, DEFAULT_HEAP_STARTING_SIZE,
+        },
     };
 
     use super::VM;
+    use crate::instruction::Opcode;
 
     #[test]
     fn test_create_vm() {
@@ -463,6 +1885,107 @@ mod tests {
         assert_eq!(test_vm.registers[0], 0);
     }
 
+    #[test]
+    fn test_run_with_callback() {
+        let mut test_vm = get_test_vm();
+        // load $0 #12, jmp $0, load $1 #1 (skipped), hlt
+        test_vm.program = vec![0, 0, 0, 12, 6, 0, 0, 0, 0, 1, 0, 1, 5, 0, 0, 0];
+        let mut pcs = vec![];
+        test_vm.run_with_callback(|vm| pcs.push(vm.pc));
+        assert_eq!(pcs, vec![4, 12, 13]);
+        assert_eq!(test_vm.registers[1], 10);
+    }
+
+    #[test]
+    fn test_instruction_width_check_passes() {
+        let mut test_vm = get_test_vm();
+        let (_, p) = program(
+            "load $0 #12\nshl $0\nshr $0 #2\naloc $1\nload32 $2 #70000\nadd $0 $1 $3\nhlt\n",
+        )
+        .unwrap();
+        test_vm.program = prepend_header(p.to_bytes(&SymbolTable::new()));
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 70000);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "misaligned instruction")]
+    fn test_instruction_width_check_catches_misaligned_jump() {
+        let mut test_vm = get_test_vm();
+        // load $0 #6, jmp $0 lands in the middle of the jmp instruction
+        test_vm.program = vec![0, 0, 0, 6, 6, 0, 0, 0, 5, 0, 0, 0];
+        test_vm.run_once();
+        test_vm.run_once();
+        test_vm.run_once();
+    }
+
+    #[test]
+    fn test_instruction_width_check_can_be_disabled() {
+        let mut test_vm = get_test_vm().with_instruction_width_check(false);
+        test_vm.program = vec![0, 0, 0, 6, 6, 0, 0, 0, 5, 0, 0, 0];
+        test_vm.run_once();
+        test_vm.run_once();
+        test_vm.run_once();
+        // Decodes [0, 0, 5, 0] from the middle of the jmp as a load
+        assert_eq!(test_vm.registers[0], 0x500);
+        assert_eq!(test_vm.pc, 10);
+    }
+
+    #[test]
+    fn test_register_handler() {
+        let mut test_vm = get_test_vm();
+        // 200 isn't an opcode the VM knows, so without a handler it would decode as IGL
+        test_vm.register_handler(200, |vm: &mut VM| {
+            let register = vm.next_8_bits() as usize;
+            vm.registers[register] = vm.next_16_bits() as i32 * 2;
+        });
+        test_vm.program = prepend_header(vec![200, 3, 0, 21, 0, 4, 0, 1, 5, 0, 0, 0]);
+        test_vm.run();
+        assert_eq!(test_vm.registers[3], 42);
+        assert_eq!(test_vm.registers[4], 1);
+
+        // Built-ins can be replaced too
+        let mut test_vm = get_test_vm();
+        test_vm.register_handler(Opcode::LOAD, |vm: &mut VM| {
+            vm.next_8_bits();
+            vm.next_16_bits();
+        });
+        test_vm.program = vec![0, 0, 0, 99];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 5);
+    }
+
+    #[test]
+    fn test_implemented_opcodes() {
+        assert!(VM::implemented_opcodes().contains(&Opcode::LOAD));
+        assert!(!VM::unimplemented_opcodes().contains(&Opcode::LOAD));
+        assert!(VM::unimplemented_opcodes().contains(&Opcode::CLOOP));
+        assert!(!VM::implemented_opcodes().contains(&Opcode::CLOOP));
+    }
+
+    #[test]
+    fn test_unimplemented_opcode_keeps_alignment() {
+        let mut test_vm = get_test_vm();
+        // djmpe (not implemented yet) followed by a load
+        assert!(!super::IMPLEMENTED_OPCODES.contains(&Opcode::DJMPE));
+        test_vm.program = vec![20, 0, 1, 2, 0, 3, 0, 9];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[3], 9);
+    }
+
+    #[test]
+    fn test_builtin_without_arm_keeps_alignment() {
+        let mut test_vm = get_test_vm();
+        // cloop with the pc already past its opcode, as `execute_instruction` leaves it
+        test_vm.program = vec![40, 0, 1, 2, 0, 3, 0, 9];
+        test_vm.pc = 1;
+        assert!(test_vm.execute_builtin(Opcode::CLOOP).is_none());
+        assert_eq!(test_vm.pc, 4);
+    }
+
     #[test]
     fn test_hlt_opcode() {
         let mut test_vm = VM::new();
@@ -483,7 +2006,7 @@ mod tests {
     #[test]
     fn test_load_opcode() {
         let mut test_vm = VM::new();
-        test_vm.program = vec![0, 0, 1, 244]; // Remember, this is how we represent 500 using two u8 in little endian format
+        test_vm.program = vec![0, 0, 1, 244]; // Remember, this is how we represent 500 using two u8 in big endian format
                                               // [0, 0, 1, 244] => next_16_bits() return the 0x100_000_000 + 244 = 256 + 244 = 500
         test_vm.program = prepend_header(test_vm.program);
         test_vm.run();
@@ -499,6 +2022,67 @@ mod tests {
         assert_eq!(test_vm.registers[2], 15);
     }
 
+    #[test]
+    fn test_bitwise_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 0b1100;
+        test_vm.registers[1] = 0b1010;
+        let (_, p) = program("and $0 $1 $2\nor $0 $1 $3\nxor $0 $1 $4\nnot $0 $5\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        for _ in 0..4 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.registers[2], 0b1000);
+        assert_eq!(test_vm.registers[3], 0b1110);
+        assert_eq!(test_vm.registers[4], 0b0110);
+        assert_eq!(test_vm.registers[5], !0b1100);
+        assert_eq!(test_vm.pc, 16);
+    }
+
+    #[test]
+    fn test_call_and_ret_opcodes() {
+        let mut test_vm = get_test_vm();
+        let (_, p) = program("load $1 #80\ncall $1\nhlt\ninc $0\nret\n").unwrap();
+        test_vm.program = prepend_header(p.to_bytes(&SymbolTable::new()));
+        let result = test_vm.run_summary();
+        assert!(result.is_success());
+        assert_eq!(test_vm.registers[0], 6);
+        assert!(test_vm.stack.is_empty());
+
+        let mut test_vm = get_test_vm();
+        test_vm.program = prepend_header(vec![47, 0, 0, 0]);
+        assert_eq!(test_vm.run_summary().code, CRASH_STACK_UNDERFLOW);
+    }
+
+    #[test]
+    fn test_inc_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0, 2, 0, 41, 18, 2, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], 42);
+    }
+
+    #[test]
+    fn test_dec_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[3] = 10;
+        test_vm.program = vec![19, 3, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[3], 9);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_addi_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![67, 2, 1, 3, 67, 0, 0, 0xFE];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 13);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 3);
+    }
+
     #[test]
     fn test_sub_opcode() {
         let mut test_vm = get_test_vm();
@@ -535,6 +2119,108 @@ mod tests {
         assert_eq!(test_vm.registers[2], 2);
     }
 
+    #[test]
+    fn test_div_by_zero() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #7\nload $1 #0\ndiv $0 $1 $2\nhlt")
+            .unwrap();
+        let mut test_vm = VM::new();
+        test_vm.program = program;
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_DIVIDE_BY_ZERO
+            }
+        ));
+        assert_eq!(test_vm.registers[2], 0);
+    }
+
+    #[test]
+    fn test_mod_opcode() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #17\nload $1 #5\nmod $0 $1 $2\nhlt")
+            .unwrap();
+        let mut test_vm = VM::new();
+        test_vm.program = program;
+        assert!(test_vm.run_summary().is_success());
+        assert_eq!(test_vm.registers[2], 2);
+
+        // The result takes the sign of the dividend
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -17;
+        test_vm.registers[1] = 5;
+        test_vm.program = vec![71, 0, 1, 2];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], -2);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_mod_by_zero() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #7\nload $1 #0\nmod $0 $1 $2\nhlt")
+            .unwrap();
+        let mut test_vm = VM::new();
+        test_vm.program = program;
+        let result = test_vm.run_summary();
+        assert_eq!(result.outcome, RunOutcome::Crashed);
+        assert_eq!(result.code, CRASH_DIVIDE_BY_ZERO);
+        assert_eq!(test_vm.registers[2], 0);
+    }
+
+    #[test]
+    fn test_mov_opcode() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #42\nmov $1 $0\nhlt")
+            .unwrap();
+        assert_eq!(&program[PIE_HEADER_LENGTH + 8..][..4], &[72, 1, 0, 0]);
+        let mut test_vm = VM::new();
+        test_vm.program = program;
+        assert!(test_vm.run_summary().is_success());
+        assert_eq!(test_vm.registers[0], 42);
+        assert_eq!(test_vm.registers[1], 42);
+    }
+
+    #[test]
+    fn test_arithmetic_overflow() {
+        // load32 $0 #2147483647, then one arithmetic instruction on it
+        for (code, expected) in [
+            ([1, 0, 1, 2], i32::MIN + 1),
+            ([3, 0, 1, 2], -2),
+            ([67, 2, 0, 1], i32::MIN),
+            ([18, 0, 0, 0], i32::MIN),
+        ] {
+            let mut program = vec![55, 0, 0, 0, 0x7F, 0xFF, 0xFF, 0xFF];
+            program.extend_from_slice(&code);
+            program.extend_from_slice(&[5, 0, 0, 0]);
+
+            let mut test_vm = get_test_vm();
+            test_vm.registers[1] = 2;
+            test_vm.program = prepend_header(program.clone());
+            let result = test_vm.run_summary();
+            assert_eq!(result.code, CRASH_ARITHMETIC_OVERFLOW, "{:?}", code);
+            assert_eq!(result.pc, 76);
+
+            let mut test_vm = get_test_vm().with_wrapping_arithmetic(true);
+            test_vm.registers[1] = 2;
+            test_vm.program = prepend_header(program);
+            assert!(test_vm.run_summary().is_success());
+            let dst = if code[0] == 18 { 0 } else { 2 };
+            assert_eq!(test_vm.registers[dst], expected, "{:?}", code);
+        }
+
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = i32::MIN;
+        test_vm.registers[1] = -1;
+        test_vm.program = prepend_header(vec![2, 0, 5, 2, 4, 0, 1, 3]);
+        assert_eq!(test_vm.run_summary().code, CRASH_ARITHMETIC_OVERFLOW);
+    }
+
     #[test]
     fn test_jmp_opcode() {
         let mut test_vm = get_test_vm();
@@ -553,6 +2239,22 @@ mod tests {
         assert_eq!(test_vm.pc, 4);
     }
 
+    #[test]
+    fn test_jmpf_out_of_bounds() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 100;
+        test_vm.program = prepend_header(vec![7, 0, 0, 0, 5, 0, 0, 0]);
+        let result = test_vm.run_summary();
+        assert_eq!(result.outcome, RunOutcome::Crashed);
+        assert_eq!(result.code, CRASH_JUMP_OUT_OF_BOUNDS);
+        assert_eq!(result.pc, 68);
+
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -4;
+        test_vm.program = prepend_header(vec![7, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(test_vm.run_summary().code, CRASH_JUMP_OUT_OF_BOUNDS);
+    }
+
     #[test]
     fn test_jmpb_opcode() {
         let mut test_vm = get_test_vm();
@@ -590,6 +2292,84 @@ mod tests {
         assert_eq!(test_vm.pc, 7);
     }
 
+    #[test]
+    fn test_events_json() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = prepend_header(vec![5, 0, 0, 0]);
+        let json = events_to_json(&test_vm.run());
+        assert!(
+            json.starts_with("[{\"event\":\"Start\",\"at\":\""),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains("{\"event\":\"GracefulStop\",\"code\":0,\"at\":\""),
+            "{}",
+            json
+        );
+        assert!(json.contains(&format!("\"application_id\":\"{}\"", test_vm.id)));
+
+        let timestamps: Vec<&str> = json
+            .split("\"at\":\"")
+            .skip(1)
+            .map(|rest| rest.split('"').next().unwrap())
+            .collect();
+        assert_eq!(timestamps.len(), 2);
+        for timestamp in timestamps {
+            assert!(
+                DateTime::parse_from_rfc3339(timestamp).is_ok(),
+                "{}",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        let mut test_vm = get_test_vm().with_instruction_limit(100);
+        // load $0 #68, jmp $0
+        test_vm.program = prepend_header(vec![0, 0, 0, 68, 6, 0, 0, 0]);
+        let result = test_vm.run_summary();
+        assert_eq!(result.outcome, RunOutcome::LimitReached);
+        assert!(!result.is_success());
+        assert_eq!(test_vm.instruction_count(), 100);
+        assert!(matches!(
+            result.events.last().unwrap().event,
+            VMEventType::InstructionLimitReached
+        ));
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut test_vm = get_test_vm();
+        // load $0 #1, load $1 #2, add $0 $1 $2, hlt
+        test_vm.program = prepend_header(vec![0, 0, 0, 1, 0, 1, 0, 2, 1, 0, 1, 2, 5, 0, 0, 0]);
+        test_vm.add_breakpoint(76);
+        let result = test_vm.run_summary();
+        assert_eq!(result.outcome, RunOutcome::Paused);
+        assert_eq!(result.pc, 76);
+        assert_eq!(test_vm.registers[0..3], [1, 2, 0]);
+
+        assert_eq!(test_vm.resume(), RunOutcome::Halted);
+        assert_eq!(test_vm.registers[2], 3);
+
+        assert!(test_vm.remove_breakpoint(76));
+        assert!(!test_vm.remove_breakpoint(76));
+        assert!(test_vm.run_summary().is_success());
+    }
+
+    #[test]
+    fn test_jmpe_not_taken() {
+        let mut test_vm = get_test_vm();
+        test_vm.equal_flag = false;
+        test_vm.registers[0] = 0;
+        // jmpe $0, load $1 #3, hlt
+        test_vm.program = prepend_header(vec![15, 0, 0, 0, 0, 1, 0, 3, 5, 0, 0, 0]);
+        assert!(test_vm.run_summary().is_success());
+        assert_eq!(test_vm.registers[1], 3);
+        assert_eq!(test_vm.instruction_count(), 3);
+    }
+
     #[test]
     fn test_neq_opcdoe() {
         let mut test_vm = get_test_vm();
@@ -686,6 +2466,645 @@ mod tests {
         // TODO: How can we validate the output since it is just printing to stdout in a test?
     }
 
+    #[test]
+    fn test_prti_opcode() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #1234\nprti $0\nhlt")
+            .unwrap();
+        let mut test_vm = VM::new();
+        test_vm.program = program;
+        assert!(test_vm.run_summary().is_success());
+        assert_eq!(test_vm.registers[0], 1234);
+
+        // Negative values print too
+        let mut test_vm = get_test_vm();
+        test_vm.registers[3] = -7;
+        test_vm.program = vec![73, 3, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_ro_string_index_matches_scan() {
+        let mut test_vm = VM::new();
+        // "Hi", "", "there", then an unterminated tail
+        test_vm.set_ro_data(b"Hi\0\0there\0tail".to_vec());
+        assert_eq!(test_vm.ro_string_ends.len(), 3);
+        for offset in 0..=test_vm.ro_data.len() + 1 {
+            assert_eq!(
+                test_vm.ro_string(offset),
+                test_vm.scan_ro_string(offset),
+                "offset {}",
+                offset
+            );
+        }
+        assert_eq!(test_vm.ro_string(4), Some(&b"there"[..]));
+        assert_eq!(test_vm.ro_string(5), Some(&b"here"[..]));
+        assert_eq!(test_vm.ro_string(10), None);
+
+        test_vm.reset();
+        assert!(test_vm.ro_string_ends.is_empty());
+    }
+
+    #[test]
+    fn test_prts_unterminated() {
+        // "Hi" without its null terminator, then a start offset past the end of the section
+        for code in [vec![21, 0, 0, 0], vec![21, 0, 9, 0]] {
+            let mut test_vm = get_test_vm();
+            test_vm.program = link(vec![Object::new(code, b"Hi".to_vec())]).unwrap();
+            let result = test_vm.run_summary();
+            assert_eq!(result.outcome, RunOutcome::Crashed);
+            assert_eq!(result.code, CRASH_RO_OUT_OF_BOUNDS);
+        }
+    }
+
+    #[test]
+    fn test_load_new_program_keeps_registers() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[3] = 42;
+        test_vm.equal_flag = true;
+        test_vm.program = vec![0, 1, 0, 7];
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+
+        test_vm.load_new_program(prepend_header(vec![5, 0, 0, 0]));
+        assert_eq!(test_vm.registers[1], 7);
+        assert_eq!(test_vm.registers[3], 42);
+        assert_eq!(test_vm.pc, 68);
+        assert_eq!(test_vm.equal_flag, false);
+        assert_eq!(test_vm.program.len(), 72);
+    }
+
+    #[test]
+    fn test_syscall_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.register_syscall(0, |vm| vm.registers[5] = 99);
+        let (_, p) = program("syscall #0\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        assert_eq!(test_vm.program, vec![48, 0, 0, 0]);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[5], 99);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_syscall_unknown_number_crashes() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = prepend_header(vec![48, 0, 7, 0, 5, 0, 0, 0]);
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_UNKNOWN_SYSCALL
+            }
+        ));
+    }
+
+    #[test]
+    fn test_validate_program() {
+        let mut test_vm = VM::new();
+        test_vm.program = prepend_header(vec![0, 0, 0, 1, 5, 0, 0, 0]);
+        assert_eq!(test_vm.validate_program(), Ok(()));
+
+        test_vm.program = prepend_header(vec![0, 0, 0, 1, 200, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(test_vm.validate_program(), Err(vec![(72, 200)]));
+        // Nothing was executed
+        assert_eq!(test_vm.pc, 0);
+    }
+
+    #[test]
+    fn test_fill_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 4;
+        test_vm.registers[1] = 10;
+        test_vm.registers[2] = 0xFF;
+        test_vm.program = vec![49, 0, 1, 2];
+        test_vm.run_once();
+        assert_eq!(test_vm.heap[3], 0);
+        assert!(test_vm.heap[4..14].iter().all(|b| *b == 0xFF));
+        assert_eq!(test_vm.heap[14], 0);
+    }
+
+    #[test]
+    fn test_fill_opcode_out_of_range() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 60;
+        test_vm.registers[1] = 10;
+        test_vm.registers[2] = 0xFF;
+        test_vm.program = prepend_header(vec![49, 0, 1, 2, 5, 0, 0, 0]);
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_HEAP_OUT_OF_BOUNDS
+            }
+        ));
+        assert!(test_vm.heap.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_deterministic_mode() {
+        let (_, p) = program("time $0\nrand $1\nrand $2\nhlt\n").unwrap();
+        let bytes = prepend_header(p.to_bytes(&SymbolTable::new()));
+        let mut first = VM::new().with_deterministic(true);
+        first.add_bytes(bytes.clone());
+        first.run();
+        let mut second = VM::new().with_deterministic(true);
+        second.add_bytes(bytes);
+        second.run();
+        assert_eq!(first.registers, second.registers);
+        assert_eq!(first.registers[0], 0);
+        assert_ne!(first.registers[1], first.registers[2]);
+
+        let mut test_vm = VM::new().with_deterministic(true);
+        test_vm.add_bytes(prepend_header(vec![60, 0, 0, 0]));
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_NONDETERMINISTIC
+            }
+        ));
+    }
+
+    #[test]
+    fn test_min_max_opcodes() {
+        let mut test_vm = get_test_vm();
+        let (_, p) = program("min $2 $0 $1\nmax $3 $0 $1\nmin $4 $1 $0\nmax $5 $1 $0\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        test_vm.run_to_completion();
+        assert_eq!(test_vm.registers[2], 5);
+        assert_eq!(test_vm.registers[3], 10);
+        assert_eq!(test_vm.registers[4], 5);
+        assert_eq!(test_vm.registers[5], 10);
+    }
+
+    #[test]
+    fn test_float_min_max_clamp_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.float_registers[2] = f64::NAN;
+        test_vm.float_registers[3] = 7.5;
+        let (_, p) = program(
+            "minf64 $4 $0 $1\nmaxf64 $5 $0 $1\nminf64 $6 $2 $1\nmaxf64 $7 $1 $2\nclampf64 $3 $0 $1\n",
+        )
+        .unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        test_vm.run_to_completion();
+        assert_eq!(test_vm.float_registers[4], 5.0);
+        assert_eq!(test_vm.float_registers[5], 10.0);
+        // NaN loses to any number
+        assert_eq!(test_vm.float_registers[6], 10.0);
+        assert_eq!(test_vm.float_registers[7], 10.0);
+        assert_eq!(test_vm.float_registers[3], 7.5);
+
+        test_vm.float_registers[3] = 12.0;
+        test_vm.program = vec![66, 3, 0, 1];
+        test_vm.pc = 0;
+        test_vm.run_once();
+        assert_eq!(test_vm.float_registers[3], 10.0);
+
+        // A NaN bound is rejected like an inverted range
+        test_vm.program = prepend_header(vec![66, 3, 0, 2]);
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_INVALID_RANGE
+            }
+        ));
+    }
+
+    #[test]
+    fn test_clamp_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 10;
+        test_vm.registers[2] = 20;
+        test_vm.program = vec![61, 0, 1, 2];
+        for (value, expected) in [(5, 10), (15, 15), (25, 20)] {
+            test_vm.registers[0] = value;
+            test_vm.pc = 0;
+            test_vm.run_once();
+            assert_eq!(test_vm.registers[0], expected);
+        }
+    }
+
+    #[test]
+    fn test_clamp_opcode_inverted_range() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 20;
+        test_vm.registers[2] = 10;
+        test_vm.program = prepend_header(vec![61, 0, 1, 2]);
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_INVALID_RANGE
+            }
+        ));
+        assert_eq!(test_vm.registers[0], 5);
+    }
+
+    #[test]
+    fn test_pusha_popa_opcodes() {
+        let mut test_vm = get_test_vm();
+        let saved = test_vm.registers;
+        // pusha, load $0 #99, load $31 #7, popa
+        test_vm.program = vec![56, 0, 0, 0, 0, 0, 0, 99, 0, 31, 0, 7, 57, 0, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.stack.len(), 128);
+        test_vm.run_once();
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 99);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers, saved);
+        assert!(test_vm.stack.is_empty());
+        assert_eq!(test_vm.pc, 16);
+    }
+
+    #[test]
+    fn test_popa_underflow() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = prepend_header(vec![57, 0, 0, 0]);
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_STACK_UNDERFLOW
+            }
+        ));
+        assert_eq!(test_vm.registers[0], 5);
+    }
+
+    #[test]
+    fn test_run_without_program() {
+        let events = VM::new().run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_NO_PROGRAM
+            }
+        ));
+    }
+
+    #[test]
+    fn test_verify_header() {
+        let mut test_vm = VM::new();
+        assert!(!test_vm.verify_header());
+        test_vm.program = vec![45, 50, 49];
+        assert!(!test_vm.verify_header());
+        assert_eq!(test_vm.run_summary().code, CRASH_BAD_HEADER);
+
+        // The prefix alone, or a header without the slot after it, is cut short
+        test_vm.program = PIE_HEADER_PREFIX.to_vec();
+        assert!(!test_vm.verify_header());
+        test_vm.program = prepend_header(vec![])[..PIE_HEADER_LENGTH].to_vec();
+        assert!(!test_vm.verify_header());
+        assert_eq!(test_vm.run_summary().code, CRASH_BAD_HEADER);
+
+        test_vm.program = prepend_header(vec![5, 0, 0, 0]);
+        assert!(test_vm.verify_header());
+        assert!(test_vm.run_summary().is_success());
+    }
+
+    #[test]
+    fn test_from_bytecode() {
+        let program = Assembler::new()
+            .assemble(".data\n.code\nload $0 #7\nhlt\n")
+            .unwrap();
+        let mut test_vm = VM::from_bytecode(program.clone()).unwrap();
+        assert!(test_vm.run_summary().is_success());
+        assert_eq!(test_vm.registers[0], 7);
+        assert!(run_program(program.clone()).unwrap().is_success());
+
+        // load $0 #7, hlt without a header
+        let headerless = vec![0, 0, 0, 7, 5, 0, 0, 0];
+        assert_eq!(
+            VM::from_bytecode(headerless.clone()).unwrap_err(),
+            HeaderError::MissingPrefix
+        );
+        assert!(run_program(headerless).is_err());
+
+        let truncated = program[..PIE_HEADER_LENGTH].to_vec();
+        assert_eq!(
+            VM::from_bytecode(truncated).unwrap_err(),
+            HeaderError::Truncated {
+                len: PIE_HEADER_LENGTH
+            }
+        );
+        assert_eq!(VM::from_bytecode(vec![]).unwrap_err(), HeaderError::Empty);
+    }
+
+    #[test]
+    fn test_header() {
+        let mut test_vm = VM::new();
+        assert_eq!(test_vm.header(), None);
+        let mut asm = Assembler::new();
+        test_vm.program = asm.assemble(".data\n.code\nhlt\n").unwrap();
+        let header = test_vm.header().unwrap();
+        assert_eq!(header.len(), PIE_HEADER_LENGTH);
+        assert_eq!(header[0..4], PIE_HEADER_PREFIX);
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = prepend_header(vec![5, 0, 0, 0]);
+        let result = test_vm.run_summary();
+        assert!(result.is_success());
+        assert_eq!(result.outcome, RunOutcome::Halted);
+        assert_eq!(result.code, 0);
+        assert_eq!(result.events.len(), 2);
+
+        let mut test_vm = get_test_vm();
+        test_vm.program = prepend_header(vec![0, 0, 0, 1, 57, 0, 0, 0]);
+        let result = test_vm.run_summary();
+        assert!(!result.is_success());
+        assert_eq!(result.outcome, RunOutcome::Crashed);
+        assert_eq!(result.code, CRASH_STACK_UNDERFLOW);
+        assert_eq!(result.pc, 72);
+    }
+
+    #[test]
+    fn test_load32_opcode() {
+        let mut test_vm = get_test_vm();
+        let (_, p) = program("load32 $0 #100000\nload32 $1 #-2\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        assert_eq!(test_vm.program.len(), 16);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 100000);
+        // The immediate's slot is skipped rather than decoded as an instruction
+        assert_eq!(test_vm.pc, 8);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[1], -2);
+        assert_eq!(test_vm.pc, 16);
+        // -2 is 0xFFFFFFFE, whose first byte would be flagged as illegal if it were decoded
+        test_vm.program = prepend_header(test_vm.program.clone());
+        assert_eq!(test_vm.validate_program(), Ok(()));
+    }
+
+    #[test]
+    fn test_loadmo_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 16];
+        test_vm.heap[12..16].copy_from_slice(&7i32.to_be_bytes());
+        test_vm.heap[4..8].copy_from_slice(&(-3i32).to_be_bytes());
+        test_vm.registers[1] = 8;
+        let (_, p) = program("loadmo $0 $1 #4\nloadmo $2 $1 #-4\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        assert_eq!(test_vm.program, vec![54, 0, 1, 4, 54, 2, 1, 0xFC]);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 7);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], -3);
+    }
+
+    #[test]
+    fn test_loadmo_opcode_out_of_range() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 16];
+        test_vm.registers[1] = 14;
+        test_vm.program = prepend_header(vec![54, 0, 1, 0]);
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_HEAP_OUT_OF_BOUNDS
+            }
+        ));
+    }
+
+    #[test]
+    fn test_lui_opcode() {
+        let mut test_vm = get_test_vm();
+        let (_, p) = program("load $0 #43981\nlui $0 #4660\nload $1 #1\nlui $1 #65535\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        for _ in 0..4 {
+            test_vm.run_once();
+        }
+        assert_eq!(test_vm.registers[0], 0x1234ABCD);
+        assert_eq!(test_vm.registers[1], 0xFFFF0001_u32 as i32);
+    }
+
+    #[test]
+    fn test_heapsz_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 100;
+        let (_, p) = program("aloc $0\nheapsz $1\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        test_vm.run_once();
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[1], 164);
+        assert_eq!(test_vm.pc, 8);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut test_vm = get_test_vm();
+        let (_, p) = program("load $0 #7\nhlt\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        assert!(!test_vm.step());
+        assert_eq!(test_vm.pc(), 4);
+        assert_eq!(test_vm.registers[0], 7);
+        assert!(test_vm.step());
+    }
+
+    #[test]
+    fn test_step_over_breakpoint() {
+        let mut test_vm = get_test_vm();
+        let (_, p) = program("load $0 #7\nload $1 #8\nhlt\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        test_vm.add_breakpoint(4);
+        assert!(!test_vm.step());
+        assert!(!test_vm.step());
+        assert_eq!(test_vm.pc(), 8);
+        assert_eq!(test_vm.registers[1], 8);
+        assert!(test_vm.step());
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut test_vm = get_test_vm();
+        let (_, p) =
+            program("load $0 #3\naloc $0\npusha\nload $1 #9\neq $0 $1\nloadf64 $2 #4\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        test_vm.run_once();
+        test_vm.run_once();
+        let snapshot = test_vm.snapshot();
+
+        for _ in 0..4 {
+            test_vm.run_once();
+        }
+        assert_ne!(test_vm.snapshot(), snapshot);
+
+        test_vm.restore(snapshot.clone());
+        assert_eq!(test_vm.snapshot(), snapshot);
+        assert_eq!(test_vm.pc, 8);
+        assert_eq!(test_vm.heap.len(), DEFAULT_HEAP_STARTING_SIZE + 3);
+        assert!(test_vm.stack.is_empty());
+        // Back to what `get_test_vm` set, before `load $1 #9`
+        assert_eq!(test_vm.registers[1], 10);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut test_vm = get_test_vm().with_instruction_limit(100);
+        test_vm.float_registers[1] = 2.5;
+        let (_, p) = program("aloc $0\npusha\neq $0 $0\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        for _ in 0..3 {
+            test_vm.run_once();
+        }
+        test_vm.reset();
+        assert!(test_vm.program.is_empty());
+        assert_eq!(test_vm.registers, [0; 32]);
+        assert_eq!(test_vm.float_registers, [0.0; 32]);
+        assert_eq!(test_vm.heap.len(), DEFAULT_HEAP_STARTING_SIZE);
+        assert!(test_vm.stack.is_empty());
+        assert_eq!(test_vm.pc, 0);
+        assert!(!test_vm.equal_flag);
+        assert_eq!(test_vm.instruction_count(), 0);
+        assert_eq!(test_vm.max_instructions, Some(100));
+    }
+
+    #[test]
+    fn test_tas_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 8];
+        test_vm.registers[1] = 4;
+        let (_, p) = program("tas $2 $1\ntas $3 $1\n").unwrap();
+        test_vm.program = p.to_bytes(&SymbolTable::new());
+        test_vm.run_once();
+        assert!(test_vm.equal_flag);
+        assert_eq!(test_vm.registers[2], 0);
+        assert_eq!(test_vm.heap[4..8], 1i32.to_be_bytes());
+        test_vm.run_once();
+        assert!(!test_vm.equal_flag);
+        assert_eq!(test_vm.registers[3], 1);
+        assert_eq!(test_vm.pc, 8);
+
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 8];
+        test_vm.registers[1] = 6;
+        test_vm.program = prepend_header(vec![68, 2, 1, 0]);
+        let events = test_vm.run();
+        assert!(matches!(
+            events.last().unwrap().event,
+            VMEventType::Crash {
+                code: CRASH_HEAP_OUT_OF_BOUNDS
+            }
+        ));
+    }
+
+    #[test]
+    fn test_run_loads_ro_data() {
+        let mut asm = Assembler::new();
+        let bytecode = asm
+            .assemble(".data\nhi: .asciiz 'hi'\n.code\nprts @hi\nhlt\n")
+            .unwrap();
+        let object = Object::new(
+            asm.code_section(&bytecode).to_vec(),
+            asm.ro_section().to_vec(),
+        );
+        let mut test_vm = get_test_vm();
+        test_vm.program = link(vec![object]).unwrap();
+        assert!(test_vm.run_summary().is_success());
+        assert_eq!(test_vm.ro_data, b"hi\0");
+
+        // A header claiming more RO data than the program holds is rejected
+        let mut test_vm = get_test_vm();
+        test_vm.program = prepend_header(vec![5, 0, 0, 0]);
+        test_vm.program[7] = 100;
+        assert_eq!(test_vm.run_summary().code, CRASH_BAD_HEADER);
+    }
+
+    #[test]
+    fn test_copyro_opcode() {
+        let mut asm = Assembler::new();
+        let bytecode = asm
+            .assemble(".data\npad: .asciiz 'x'\nhi: .asciiz 'Hello'\n.code\ncopyro $0 $1 $2\n")
+            .unwrap();
+        let mut test_vm = get_test_vm();
+        test_vm.ro_data = asm.ro.clone();
+        test_vm.program = asm.code_section(&bytecode).to_vec();
+        test_vm.registers[0] = 8;
+        test_vm.registers[1] = asm.symbols.symbol_value("hi").unwrap() as i32;
+        test_vm.registers[2] = 6;
+        test_vm.run_once();
+        assert_eq!(&test_vm.heap[8..14], b"Hello\0");
+        assert_eq!(test_vm.heap[14], 0);
+
+        // Reading past the end of the RO section or writing past the end of the heap both crash
+        for (heap_addr, ro_offset, code) in [
+            (0, 3, CRASH_RO_OUT_OF_BOUNDS),
+            (60, 0, CRASH_HEAP_OUT_OF_BOUNDS),
+        ] {
+            let mut test_vm = get_test_vm();
+            test_vm.registers[0] = heap_addr;
+            test_vm.registers[1] = ro_offset;
+            test_vm.registers[2] = 6;
+            test_vm.program = link(vec![Object::new(vec![69, 0, 1, 2], asm.ro.clone())]).unwrap();
+            assert_eq!(test_vm.run_summary().code, code);
+        }
+    }
+
+    #[test]
+    fn test_core_dump_on_crash() {
+        let path = std::env::temp_dir().join(format!("lrvm_core_{}.txt", std::process::id()));
+        let mut test_vm = get_test_vm().with_core_dump_path(path.clone());
+        test_vm.registers[0] = 60;
+        test_vm.registers[1] = 10;
+        // load, then a FILL that runs off the end of the heap
+        test_vm.program = prepend_header(vec![0, 2, 0, 255, 49, 0, 1, 2, 5, 0, 0, 0]);
+        test_vm.run();
+        assert_eq!(test_vm.pc, 72);
+
+        let dump = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(dump.contains("pc: 72\n"), "dump: {}", dump);
+        assert!(dump.contains("Crash { code: 3 }"), "dump: {}", dump);
+    }
+
+    #[test]
+    fn test_rol_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 0x80000000_u32 as i32;
+        test_vm.program = vec![50, 0, 0, 1];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 1);
+    }
+
+    #[test]
+    fn test_ror_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 1;
+        test_vm.program = vec![51, 0, 0, 1];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 0x80000000_u32 as i32);
+    }
+
+    #[test]
+    fn test_clz_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 1;
+        test_vm.registers[1] = 0;
+        test_vm.program = vec![52, 2, 0, 0, 52, 3, 1, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 31);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[3], 32);
+        assert_eq!(test_vm.pc, 8);
+    }
+
+    #[test]
+    fn test_popcnt_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 0b1011;
+        test_vm.program = vec![53, 2, 0, 0];
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 3);
+    }
+
     #[test]
     fn test_shl_opcode() {
         let mut test_vm = get_test_vm();